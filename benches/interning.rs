@@ -0,0 +1,87 @@
+//! Compares instruction lookups keyed on names with the interned ids the definition uses,
+//! then times scanning and running a long program end to end.
+//!
+//! Run with `cargo bench --bench interning`.
+
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+use riasm::{asm_definition::ASMDefinition, asm_interner::Interner, asm_value::ASMValue};
+
+const LINES: usize = 20_000;
+const LOOKUPS: usize = 2_000_000;
+
+fn mov(_state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    args[0].try_modify_register(args[1].resolve());
+}
+
+fn source() -> String {
+    let mut code = String::new();
+    for line in 0..LINES {
+        code.push_str(&format!("mov [a] {}\ninc [a]\n", line));
+    }
+    code.push_str("halt");
+    code
+}
+
+fn lookups() {
+    let names = ["mov", "inc", "dec", "goto", "jump_if", "halt"];
+    let mut by_name: HashMap<String, usize> = HashMap::new();
+    let mut interner = Interner::new();
+    let mut by_id: HashMap<u32, usize> = HashMap::new();
+    for (index, name) in names.iter().enumerate() {
+        by_name.insert(name.to_string(), index);
+        by_id.insert(interner.intern(name), index);
+    }
+    let ids: Vec<u32> = names.iter().map(|name| interner.intern(name)).collect();
+
+    let start = Instant::now();
+    let mut total = 0;
+    for lookup in 0..LOOKUPS {
+        total += by_name[names[lookup % names.len()]];
+    }
+    black_box(total);
+    let string_keys = start.elapsed();
+
+    let start = Instant::now();
+    let mut total = 0;
+    for lookup in 0..LOOKUPS {
+        total += by_id[&ids[lookup % ids.len()]];
+    }
+    black_box(total);
+    let symbol_keys = start.elapsed();
+
+    println!(
+        "{} lookups: {:?} by name, {:?} by interned id",
+        LOOKUPS, string_keys, symbol_keys
+    );
+}
+
+fn program() {
+    let code = source();
+    let mut definition = ASMDefinition::new()
+        .insert_stdlib()
+        .insert_instruction_with_arity("mov", 2, mov)
+        .insert_register("a");
+
+    let start = Instant::now();
+    let program = definition.program(&code).expect("benchmark program scans");
+    let scanned = start.elapsed();
+
+    let start = Instant::now();
+    program
+        .run(&mut definition)
+        .expect("benchmark program runs");
+    let ran = start.elapsed();
+
+    println!(
+        "{} instructions: scanned in {:?}, ran in {:?}",
+        LINES * 2,
+        scanned,
+        ran
+    );
+}
+
+fn main() {
+    lookups();
+    program();
+}
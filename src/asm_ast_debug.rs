@@ -0,0 +1,68 @@
+use std::fmt::Write;
+
+use crate::asm_definition::{ASMDefinition, ASTNode};
+
+pub fn ast_debug(definition: &ASMDefinition, stream: &[ASTNode]) -> String {
+    let mut output = String::new();
+    let mut in_expression = false;
+    for (address, token) in stream.iter().enumerate() {
+        match token {
+            ASTNode::ASTInstruction(id) => {
+                let _ = writeln!(output, "Expression @{}", address);
+                for label in labels_at(definition, address) {
+                    let _ = writeln!(output, "  Label {}", label);
+                }
+                let _ = writeln!(output, "  Instruction {}", definition.symbol_name(*id));
+                in_expression = true;
+            }
+            ASTNode::ASTExprEnd => {
+                if !in_expression {
+                    let _ = writeln!(output, "ExprEnd @{}", address);
+                }
+                in_expression = false;
+            }
+            argument => {
+                let _ = writeln!(
+                    output,
+                    "{}{}",
+                    indent(in_expression),
+                    describe_argument(argument)
+                );
+            }
+        }
+    }
+    output
+}
+
+fn describe_argument(argument: &ASTNode) -> String {
+    match argument {
+        ASTNode::ASTValue(value) => format!("Value {:?}", value.get_value_holder()),
+        ASTNode::ASTPooled(index, value) => {
+            format!("Pooled #{} {:?}", index, value.get_value_holder())
+        }
+        ASTNode::ASTRegister(register) => format!("Register {}", register),
+        ASTNode::ASTIndexed(base, index) => {
+            format!("Index {} of {}", index, describe_argument(base))
+        }
+        _ => String::new(),
+    }
+}
+
+fn indent(in_expression: bool) -> &'static str {
+    if in_expression {
+        "    "
+    } else {
+        ""
+    }
+}
+
+fn labels_at(definition: &ASMDefinition, address: usize) -> Vec<&String> {
+    let mut labels: Vec<&String> = definition
+        .labels
+        .iter()
+        .filter(|(_, line)| **line == address)
+        .map(|(name, _)| name)
+        .collect();
+    labels.sort();
+    labels
+}
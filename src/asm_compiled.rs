@@ -0,0 +1,478 @@
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::{
+    asm_definition::{self, ASMDefinition, ASTNode},
+    asm_error::ASMError,
+    asm_instruction::ASMInstruction,
+    asm_interner::Symbol,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Value(ASMValue),
+    Register(String),
+    Indexed(Box<Operand>, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opcode {
+    pub instruction: usize,
+    pub args: Vec<Operand>,
+}
+
+#[derive(Clone)]
+pub struct CompiledProgram {
+    pub instructions: Vec<ASMInstruction>,
+    pub names: Vec<Symbol>,
+    pub mnemonics: Vec<String>,
+    pub opcodes: Vec<Opcode>,
+    pub labels: HashMap<String, usize>,
+}
+
+const MAGIC: &[u8; 5] = b"RIASM";
+const FORMAT_VERSION: u8 = 1;
+/// Deepest chain of indexed operands or nested arrays `from_bytes` accepts.
+const MAX_NESTING: usize = 64;
+
+impl CompiledProgram {
+    pub fn is_bound(&self) -> bool {
+        self.instructions.len() == self.mnemonics.len()
+    }
+
+    pub fn bind(&mut self, definition: &mut ASMDefinition) -> Result<(), ASMError> {
+        let mut instructions: Vec<ASMInstruction> = Vec::new();
+        let mut names: Vec<Symbol> = Vec::new();
+        for mnemonic in self.mnemonics.iter() {
+            let name = definition.intern(mnemonic);
+            match definition.instructions.get(&name) {
+                Some(instruction) => instructions.push(instruction.clone()),
+                None => {
+                    return Err(ASMError::Exception(format!(
+                        "{} is an unknown instruction",
+                        mnemonic
+                    )))
+                }
+            }
+            names.push(name);
+        }
+        self.instructions = instructions;
+        self.names = names;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ASMError> {
+        let mut pool: Vec<ASMValueHolder> = Vec::new();
+        let mut body: Vec<u8> = Vec::new();
+        write_u32(&mut body, self.opcodes.len())?;
+        for opcode in self.opcodes.iter() {
+            write_u32(&mut body, opcode.instruction)?;
+            write_u32(&mut body, opcode.args.len())?;
+            for operand in opcode.args.iter() {
+                write_operand(&mut body, &mut pool, operand)?;
+            }
+        }
+
+        let mut output: Vec<u8> = MAGIC.to_vec();
+        output.push(FORMAT_VERSION);
+        write_u32(&mut output, self.mnemonics.len())?;
+        for mnemonic in self.mnemonics.iter() {
+            write_str(&mut output, mnemonic)?;
+        }
+        let mut labels: Vec<(&String, &usize)> = self.labels.iter().collect();
+        labels.sort();
+        write_u32(&mut output, labels.len())?;
+        for (name, opcode) in labels {
+            write_str(&mut output, name)?;
+            write_u32(&mut output, *opcode)?;
+        }
+        write_u32(&mut output, pool.len())?;
+        for value in pool.iter() {
+            write_value(&mut output, value)?;
+        }
+        output.extend(body);
+        Ok(output)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<CompiledProgram, ASMError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ASMError::Exception(
+                "not a compiled RIASM program".to_string(),
+            ));
+        }
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(ASMError::Exception(format!(
+                "compiled program format version {} is not supported",
+                version
+            )));
+        }
+        let mut mnemonics: Vec<String> = Vec::new();
+        for _ in 0..reader.u32()? {
+            mnemonics.push(reader.string()?);
+        }
+        let mut labels: HashMap<String, usize> = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            labels.insert(name, reader.u32()?);
+        }
+        let mut pool: Vec<ASMValue> = Vec::new();
+        for _ in 0..reader.u32()? {
+            pool.push(ASMValue::new_from_holder(reader.value(0)?, None));
+        }
+        let mut opcodes: Vec<Opcode> = Vec::new();
+        for _ in 0..reader.u32()? {
+            let instruction = reader.u32()?;
+            if instruction >= mnemonics.len() {
+                return Err(ASMError::Exception(format!(
+                    "opcode refers to missing instruction {}",
+                    instruction
+                )));
+            }
+            let mut args: Vec<Operand> = Vec::new();
+            for _ in 0..reader.u32()? {
+                args.push(reader.operand(&pool, 0)?);
+            }
+            opcodes.push(Opcode { instruction, args });
+        }
+        if reader.position != bytes.len() {
+            return Err(ASMError::Exception(
+                "compiled program has trailing bytes".to_string(),
+            ));
+        }
+        Ok(CompiledProgram {
+            instructions: Vec::new(),
+            names: Vec::new(),
+            mnemonics,
+            opcodes,
+            labels,
+        })
+    }
+}
+
+fn write_u32(output: &mut Vec<u8>, value: usize) -> Result<(), ASMError> {
+    match u32::try_from(value) {
+        Ok(value) => {
+            output.extend(value.to_le_bytes());
+            Ok(())
+        }
+        Err(_) => Err(ASMError::Exception(format!(
+            "{} is too large for a compiled program",
+            value
+        ))),
+    }
+}
+
+fn write_str(output: &mut Vec<u8>, value: &str) -> Result<(), ASMError> {
+    write_u32(output, value.len())?;
+    output.extend(value.as_bytes());
+    Ok(())
+}
+
+fn write_operand(
+    output: &mut Vec<u8>,
+    pool: &mut Vec<ASMValueHolder>,
+    operand: &Operand,
+) -> Result<(), ASMError> {
+    match operand {
+        Operand::Value(value) => {
+            let holder = value.get_value_holder();
+            let index = match pool.iter().position(|pooled| *pooled == holder) {
+                Some(index) => index,
+                None => {
+                    pool.push(holder);
+                    pool.len() - 1
+                }
+            };
+            output.push(0);
+            write_u32(output, index)
+        }
+        Operand::Register(name) => {
+            output.push(1);
+            write_str(output, name)
+        }
+        Operand::Indexed(base, index) => {
+            output.push(2);
+            write_operand(output, pool, base)?;
+            write_u32(output, *index)
+        }
+    }
+}
+
+fn write_value(output: &mut Vec<u8>, value: &ASMValueHolder) -> Result<(), ASMError> {
+    match value {
+        ASMValueHolder::Invalid => output.push(0),
+        ASMValueHolder::Int(value) => {
+            output.push(1);
+            output.extend(value.to_le_bytes());
+        }
+        ASMValueHolder::I8(value) => {
+            output.push(2);
+            output.extend(value.to_le_bytes());
+        }
+        ASMValueHolder::U8(value) => {
+            output.push(3);
+            output.push(*value);
+        }
+        ASMValueHolder::I64(value) => {
+            output.push(4);
+            output.extend(value.to_le_bytes());
+        }
+        ASMValueHolder::Float(value) => {
+            output.push(5);
+            output.extend(value.to_le_bytes());
+        }
+        ASMValueHolder::Str(value) => {
+            output.push(6);
+            write_str(output, value)?;
+        }
+        ASMValueHolder::Register(name) => {
+            output.push(7);
+            write_str(output, name)?;
+        }
+        ASMValueHolder::Label(name) => {
+            output.push(8);
+            write_str(output, name)?;
+        }
+        ASMValueHolder::ResolvedLabel(name, destination) => {
+            output.push(9);
+            write_str(output, name)?;
+            write_u32(output, *destination)?;
+        }
+        ASMValueHolder::Array(values) => {
+            output.push(10);
+            write_u32(output, values.len())?;
+            for value in values.iter() {
+                write_value(output, &value.get_value_holder())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ASMError> {
+        if self.bytes.len() - self.position < count {
+            return Err(ASMError::Exception(
+                "compiled program is truncated".to_string(),
+            ));
+        }
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ASMError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, ASMError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, ASMError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, ASMError> {
+        let length = self.u32()?;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| ASMError::Exception("compiled program contains invalid UTF-8".to_string()))
+    }
+
+    fn nest(depth: usize) -> Result<usize, ASMError> {
+        if depth >= MAX_NESTING {
+            return Err(ASMError::Exception(format!(
+                "compiled program nests deeper than {} levels",
+                MAX_NESTING
+            )));
+        }
+        Ok(depth + 1)
+    }
+
+    fn operand(&mut self, pool: &[ASMValue], depth: usize) -> Result<Operand, ASMError> {
+        match self.u8()? {
+            0 => {
+                let index = self.u32()?;
+                match pool.get(index) {
+                    Some(value) => Ok(Operand::Value(value.clone())),
+                    None => Err(ASMError::Exception(format!(
+                        "operand refers to missing constant {}",
+                        index
+                    ))),
+                }
+            }
+            1 => Ok(Operand::Register(self.string()?)),
+            2 => {
+                let base = self.operand(pool, Self::nest(depth)?)?;
+                Ok(Operand::Indexed(Box::new(base), self.u32()?))
+            }
+            tag => Err(ASMError::Exception(format!(
+                "{} is not a valid operand tag",
+                tag
+            ))),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<ASMValueHolder, ASMError> {
+        Ok(match self.u8()? {
+            0 => ASMValueHolder::Invalid,
+            1 => ASMValueHolder::Int(i32::from_le_bytes(self.array()?)),
+            2 => ASMValueHolder::I8(i8::from_le_bytes(self.array()?)),
+            3 => ASMValueHolder::U8(self.u8()?),
+            4 => ASMValueHolder::I64(i64::from_le_bytes(self.array()?)),
+            5 => ASMValueHolder::Float(f32::from_le_bytes(self.array()?)),
+            6 => ASMValueHolder::Str(self.string()?),
+            7 => ASMValueHolder::Register(self.string()?),
+            8 => ASMValueHolder::Label(self.string()?),
+            9 => {
+                let name = self.string()?;
+                ASMValueHolder::ResolvedLabel(name, self.u32()?)
+            }
+            10 => {
+                let mut values: Vec<ASMValue> = Vec::new();
+                let depth = Self::nest(depth)?;
+                for _ in 0..self.u32()? {
+                    values.push(ASMValue::new_from_holder(self.value(depth)?, None));
+                }
+                ASMValueHolder::Array(values)
+            }
+            tag => {
+                return Err(ASMError::Exception(format!(
+                    "{} is not a valid value tag",
+                    tag
+                )))
+            }
+        })
+    }
+}
+
+pub fn compile(definition: &mut ASMDefinition, stream: &[ASTNode]) -> CompiledProgram {
+    let instruction_starts = asm_definition::instruction_starts(stream);
+    let labels: HashMap<String, usize> = definition
+        .labels
+        .iter()
+        .map(|(name, line)| {
+            let opcode = instruction_starts.partition_point(|start| start < line);
+            (name.clone(), opcode)
+        })
+        .collect();
+
+    let mut program = CompiledProgram {
+        instructions: Vec::new(),
+        names: Vec::new(),
+        mnemonics: Vec::new(),
+        opcodes: Vec::new(),
+        labels,
+    };
+    let mut table: HashMap<Symbol, usize> = HashMap::new();
+    let mut current: Option<Opcode> = None;
+    for token in stream {
+        match token {
+            ASTNode::ASTInstruction(name) => {
+                let instruction = match table.get(name) {
+                    Some(index) => *index,
+                    None => match definition.instructions.get(name) {
+                        Some(instruction) => {
+                            program.instructions.push(instruction.clone());
+                            program.names.push(*name);
+                            program
+                                .mnemonics
+                                .push(definition.symbol_name(*name).to_string());
+                            table.insert(*name, program.names.len() - 1);
+                            program.names.len() - 1
+                        }
+                        None => {
+                            definition.raise_exception(
+                                format!(
+                                    "{} is an unknown instruction",
+                                    definition.symbol_name(*name)
+                                )
+                                .as_str(),
+                                true,
+                            );
+                            continue;
+                        }
+                    },
+                };
+                current = Some(Opcode {
+                    instruction,
+                    args: Vec::new(),
+                });
+            }
+            ASTNode::ASTExprEnd => {
+                if let Some(opcode) = current.take() {
+                    program.opcodes.push(opcode);
+                }
+            }
+            argument => {
+                let operand =
+                    compile_operand(definition, &program.labels, &instruction_starts, argument);
+                push_operand(definition, &mut current, operand);
+            }
+        }
+    }
+    program
+}
+
+/// Label destinations are token indexes in the scanned stream and become opcode
+/// indexes here, including local `1b`/`1f` references that have no entry in `labels`.
+fn compile_operand(
+    definition: &mut ASMDefinition,
+    labels: &HashMap<String, usize>,
+    instruction_starts: &[usize],
+    argument: &ASTNode,
+) -> Operand {
+    match argument {
+        ASTNode::ASTValue(value) => match value.get_value_holder() {
+            ASMValueHolder::Label(name) => match labels.get(&name) {
+                Some(destination) => Operand::Value(ASMValue::new_from_holder(
+                    ASMValueHolder::ResolvedLabel(name, *destination),
+                    None,
+                )),
+                None => Operand::Value(value.clone()),
+            },
+            ASMValueHolder::ResolvedLabel(name, line) => {
+                let opcode = instruction_starts.partition_point(|start| *start < line);
+                Operand::Value(ASMValue::new_from_holder(
+                    ASMValueHolder::ResolvedLabel(name, opcode),
+                    None,
+                ))
+            }
+            _ => Operand::Value(value.clone()),
+        },
+        ASTNode::ASTRegister(name) => {
+            if !definition.ensure_register(name) {
+                definition
+                    .raise_exception(format!("register {} is not defined", name).as_str(), true);
+            }
+            Operand::Register(name.clone())
+        }
+        ASTNode::ASTPooled(_, value) => Operand::Value((**value).clone()),
+        ASTNode::ASTIndexed(base, index) => Operand::Indexed(
+            Box::new(compile_operand(
+                definition,
+                labels,
+                instruction_starts,
+                base,
+            )),
+            *index,
+        ),
+        _ => Operand::Value(ASMValue::new_empty(None)),
+    }
+}
+
+fn push_operand(definition: &mut ASMDefinition, current: &mut Option<Opcode>, operand: Operand) {
+    match current {
+        Some(opcode) => opcode.args.push(operand),
+        None => definition.raise_exception("Operand encountered with no instruction present", true),
+    }
+}
@@ -1,27 +1,244 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    fmt,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
-    asm_instruction::ASMInstruction,
+    asm_ast_debug,
+    asm_compiled::{self, CompiledProgram, Operand},
+    asm_error::ASMError,
+    asm_instruction::{ASMClosure, ASMInstruction, ArgKind, ControlFlow, InstructionDoc},
+    asm_interner::{Interner, Symbol},
+    asm_lexer::{self, Token, TokenKind},
+    asm_listing::{self, ListingLine},
+    asm_program::Program,
+    asm_reachability, asm_stdlib,
+    asm_symbol_table::SymbolTable,
     asm_value::{ASMValue, ASMValueHolder},
 };
 
+const TIME_LIMIT_CHECK_INTERVAL: u64 = 256;
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+const DEFAULT_BANK: &str = "main";
+
 #[derive(Clone)]
 pub enum ASTNode {
     ASTValue(ASMValue),
-    ASTInstruction(String),
+    ASTInstruction(Symbol),
     ASTRegister(String),
+    ASTIndexed(Box<ASTNode>, usize),
+    /// A string or float literal deduplicated during `scan`. Every occurrence of the
+    /// literal in one scan shares the value, so the stream stays valid after later scans.
+    ASTPooled(usize, Rc<ASMValue>),
     ASTExprEnd,
 }
 
+#[derive(Clone, Copy)]
+pub enum RegisterSyntax {
+    Brackets,
+    Prefix(char),
+    Pattern(fn(&str) -> bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticMode {
+    Wrapping,
+    Saturating,
+    Checked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionReason {
+    Halted,
+    ReachedEnd,
+    Error,
+    Cancelled,
+}
+
+/// Stops a running definition from another thread. The run loop checks the flag
+/// before every step and halts with `CompletionReason::Cancelled`.
+#[derive(Clone)]
+pub struct StopHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.flag.store(true, atomic::Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
+    ConditionMet,
+    Halted,
+    ReachedEnd,
+}
+
+pub type HaltHook = Box<dyn FnMut(u64, CompletionReason)>;
+pub type InstructionHook = Box<dyn FnMut(&[ASMValue])>;
+pub type ArgRewriteHook = Box<dyn FnMut(&str, &mut Vec<ASMValue>)>;
+pub type UnknownInstructionHandler = Box<dyn FnMut(&mut ASMDefinition, &str, Vec<ASMValue>)>;
+pub type LiteralParser = Box<dyn Fn(&str) -> Option<ASMValue>>;
+
+struct RunClock {
+    started: Instant,
+    steps: u64,
+}
+
+impl RunClock {
+    fn new() -> Self {
+        RunClock {
+            started: Instant::now(),
+            steps: 0,
+        }
+    }
+}
+
+/// Destinations of numbered lines, indexed by line number minus the first line number.
+#[derive(Default)]
+struct LineTable {
+    first: i64,
+    destinations: Vec<Option<usize>>,
+}
+
+impl LineTable {
+    /// Line numbers may be at most this many times further apart on average than
+    /// contiguous ones before the table is skipped in favour of the label map.
+    const MAX_SPREAD: usize = 16;
+
+    fn build(labels: &HashMap<String, usize>) -> Self {
+        let lines: Vec<(i64, usize)> = labels
+            .iter()
+            .filter_map(|(name, destination)| {
+                let number = name.parse::<i64>().ok()?;
+                (number >= 0 && number.to_string() == *name).then_some((number, *destination))
+            })
+            .collect();
+        let (first, last) = match (
+            lines.iter().map(|line| line.0).min(),
+            lines.iter().map(|line| line.0).max(),
+        ) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return LineTable::default(),
+        };
+        let span = (last - first) as usize + 1;
+        if span > lines.len() * Self::MAX_SPREAD {
+            return LineTable::default();
+        }
+        let mut destinations = vec![None; span];
+        for (number, destination) in lines {
+            destinations[(number - first) as usize] = Some(destination);
+        }
+        LineTable {
+            first,
+            destinations,
+        }
+    }
+
+    fn get(&self, line_number: i64) -> Option<usize> {
+        let index = usize::try_from(line_number.checked_sub(self.first)?).ok()?;
+        self.destinations.get(index).copied().flatten()
+    }
+}
+
+struct PendingExpression {
+    instruction: Option<ASMInstruction>,
+    unknown: bool,
+    name: Symbol,
+    args: Vec<ASMValue>,
+}
+
+impl PendingExpression {
+    fn new() -> Self {
+        PendingExpression {
+            instruction: None,
+            unknown: false,
+            name: 0,
+            args: Vec::new(),
+        }
+    }
+
+    /// Whether an instruction, known or left to the unknown instruction handler, is
+    /// collecting arguments.
+    fn is_open(&self) -> bool {
+        self.instruction.is_some() || self.unknown
+    }
+}
+
 pub struct ASMDefinition {
     pub registers: HashMap<String, ASMValue>,
-    pub instructions: HashMap<String, ASMInstruction>,
+    pub instructions: HashMap<Symbol, ASMInstruction>,
+    symbols: Interner,
+    register_defaults: HashMap<String, ASMValue>,
+    register_banks: HashMap<String, HashMap<String, ASMValue>>,
+    active_bank: String,
     pub labels: HashMap<String, usize>,
+    local_labels: HashMap<String, Vec<usize>>,
+    pub constants: HashMap<String, ASMValue>,
     _priority: u16,
     ptr_to_self: Option<*mut ASMDefinition>,
     errors: u64,
+    raised_errors: Vec<ASMError>,
+    warnings: Vec<ASMError>,
     halted: bool,
+    exit_code: Option<i32>,
+    should_stop: Arc<AtomicBool>,
+    cancelled: bool,
     current_line: usize,
+    instruction_starts: Vec<usize>,
+    pending_jump: Option<usize>,
+    time_limit: Option<Duration>,
+    require_halt: bool,
+    line_numbers: bool,
+    line_jump_table: bool,
+    line_table: LineTable,
+    immediate_prefix: bool,
+    nested_comments: bool,
+    max_line_length: Option<usize>,
+    max_args_per_instruction: Option<usize>,
+    implicit_registers: bool,
+    bare_registers: bool,
+    rng_state: u64,
+    halt_hook: Option<HaltHook>,
+    literal_parser: Option<LiteralParser>,
+    instruction_hooks: HashMap<Symbol, InstructionHook>,
+    arg_rewrite_hook: Option<ArgRewriteHook>,
+    unknown_instruction_handler: Option<UnknownInstructionHandler>,
+    program_length: usize,
+    collect_all_errors: bool,
+    linting: bool,
+    register_syntax: RegisterSyntax,
+    output: Box<dyn Write>,
+    running: bool,
+    instruction_costs: HashMap<Symbol, u64>,
+    control_flow: HashMap<Symbol, ControlFlow>,
+    cycles: u64,
+    memory: Vec<ASMValue>,
+    frozen: bool,
+    constant_pool: Vec<Rc<ASMValue>>,
+    default_radix: u32,
+    arithmetic_mode: ArithmeticMode,
+    register_assign_mode: Option<ArithmeticMode>,
+    call_stack: Vec<usize>,
+    comparison: Option<Ordering>,
+    coverage: HashSet<usize>,
+    entry_point: Option<String>,
+    appended_program: Vec<ASTNode>,
+    loaded_program: Option<Program>,
+    scan_base: usize,
+    source_lines: Vec<usize>,
+    constant_pool_index: HashMap<String, usize>,
+    stepping: bool,
 }
 
 impl ASMDefinition {
@@ -30,8 +247,8 @@ impl ASMDefinition {
         for (regs_name, reg_val) in self.registers.iter() {
             println!("REGISTER {0} is {1}", regs_name, reg_val);
         }
-        for (instruction_name, _instruction) in self.instructions.iter() {
-            println!("FOUND INSTRUCTION: {}", instruction_name);
+        for (instruction_id, _instruction) in self.instructions.iter() {
+            println!("FOUND INSTRUCTION: {}", self.symbol_name(*instruction_id));
         }
         println!("== ASMDefinition STATE DUMP END ==")
     }
@@ -40,23 +257,318 @@ impl ASMDefinition {
         let mut def = ASMDefinition {
             registers: HashMap::new(),
             instructions: HashMap::new(),
+            symbols: Interner::new(),
+            register_defaults: HashMap::new(),
+            register_banks: HashMap::new(),
+            active_bank: DEFAULT_BANK.to_string(),
             labels: HashMap::new(),
+            local_labels: HashMap::new(),
+            constants: HashMap::new(),
             _priority: 1,
             ptr_to_self: None,
             errors: 0,
+            raised_errors: Vec::new(),
+            warnings: Vec::new(),
             halted: false,
+            exit_code: None,
+            should_stop: Arc::new(AtomicBool::new(false)),
+            cancelled: false,
             current_line: 0,
+            instruction_starts: Vec::new(),
+            pending_jump: None,
+            time_limit: None,
+            require_halt: false,
+            line_numbers: false,
+            line_jump_table: false,
+            line_table: LineTable::default(),
+            immediate_prefix: false,
+            nested_comments: false,
+            max_line_length: None,
+            max_args_per_instruction: None,
+            implicit_registers: false,
+            bare_registers: false,
+            rng_state: DEFAULT_SEED,
+            halt_hook: None,
+            literal_parser: None,
+            instruction_hooks: HashMap::new(),
+            arg_rewrite_hook: None,
+            unknown_instruction_handler: None,
+            program_length: 0,
+            collect_all_errors: false,
+            linting: false,
+            register_syntax: RegisterSyntax::Brackets,
+            output: Box::new(io::stdout()),
+            running: false,
+            instruction_costs: HashMap::new(),
+            control_flow: HashMap::new(),
+            cycles: 0,
+            memory: Vec::new(),
+            frozen: false,
+            constant_pool: Vec::new(),
+            default_radix: 10,
+            arithmetic_mode: ArithmeticMode::Checked,
+            register_assign_mode: None,
+            call_stack: Vec::new(),
+            comparison: None,
+            coverage: HashSet::new(),
+            entry_point: None,
+            appended_program: Vec::new(),
+            loaded_program: None,
+            scan_base: 0,
+            source_lines: Vec::new(),
+            constant_pool_index: HashMap::new(),
+            stepping: false,
         };
         let def_ptr: *mut ASMDefinition = &mut def;
         def.ptr_to_self = Some(def_ptr);
         def
     }
 
-    pub fn insert_register(mut self, reg_name: &str) -> Self {
-        self.registers.insert(
-            reg_name.into(),
-            ASMValue::new_empty(self.ptr_to_self.clone()),
-        );
+    pub fn insert_register(self, reg_name: &str) -> Self {
+        let default = ASMValue::new_int(0, self.ptr_to_self);
+        self.insert_register_with_default(reg_name, default)
+    }
+
+    pub fn insert_register_range(self, prefix: &str, count: usize) -> Self {
+        (0..count).fold(self, |definition, index| {
+            definition.insert_register(format!("{}{}", prefix, index).as_str())
+        })
+    }
+
+    pub fn insert_register_with_default(mut self, reg_name: &str, default: ASMValue) -> Self {
+        if let Err(error) = self.ensure_unfrozen() {
+            self.raise_error(error, false);
+            return self;
+        }
+        self.registers.insert(reg_name.into(), default.clone());
+        self.register_defaults.insert(reg_name.into(), default);
+        self
+    }
+
+    pub fn reset_register(&mut self, reg_name: &str) -> Result<(), ASMError> {
+        let default = match self.register_defaults.get(reg_name) {
+            Some(default) => default.clone(),
+            None => ASMValue::new_int(0, self.ptr_to_self),
+        };
+        self.set_register(reg_name, default)
+    }
+
+    pub fn reset_registers(&mut self) -> Result<(), ASMError> {
+        let names: Vec<String> = self.registers.keys().cloned().collect();
+        for name in names {
+            self.reset_register(&name)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a bank holding the currently declared registers at their defaults. Only the
+    /// active bank is visible to `get_register` and `set_register`; labels and memory are
+    /// shared by every bank.
+    pub fn add_register_bank(&mut self, name: &str) -> Result<(), ASMError> {
+        if name == self.active_bank || self.register_banks.contains_key(name) {
+            return Err(ASMError::Exception(format!(
+                "register bank {} is defined twice",
+                name
+            )));
+        }
+        let bank = self
+            .registers
+            .keys()
+            .map(|reg_name| {
+                let default = match self.register_defaults.get(reg_name) {
+                    Some(default) => default.clone(),
+                    None => ASMValue::new_int(0, self.ptr_to_self),
+                };
+                (reg_name.clone(), default)
+            })
+            .collect();
+        self.register_banks.insert(name.to_string(), bank);
+        Ok(())
+    }
+
+    pub fn switch_bank(&mut self, name: &str) -> Result<(), ASMError> {
+        if name == self.active_bank {
+            return Ok(());
+        }
+        let bank = match self.register_banks.remove(name) {
+            Some(bank) => bank,
+            None => {
+                return Err(ASMError::Exception(format!(
+                    "register bank {} is not defined",
+                    name
+                )))
+            }
+        };
+        let previous = std::mem::replace(&mut self.registers, bank);
+        let previous_name = std::mem::replace(&mut self.active_bank, name.to_string());
+        self.register_banks.insert(previous_name, previous);
+        Ok(())
+    }
+
+    pub fn active_bank(&self) -> &str {
+        &self.active_bank
+    }
+
+    pub fn remove_register(&mut self, reg_name: &str) -> Result<(), ASMError> {
+        self.ensure_unfrozen()?;
+        self.register_defaults.remove(reg_name);
+        match self.registers.remove(reg_name) {
+            Some(_) => Ok(()),
+            None => Err(ASMError::Exception(format!(
+                "register {} is not defined",
+                reg_name
+            ))),
+        }
+    }
+
+    pub fn set_register(&mut self, reg_name: &str, value: ASMValue) -> Result<(), ASMError> {
+        let mode = self.register_assign_mode;
+        match self.registers.get_mut(reg_name) {
+            Some(register) => {
+                *register = match mode {
+                    Some(mode) => value.assign_to(register, mode).map_err(|_| {
+                        ASMError::Exception(format!(
+                            "value {} does not fit in register {}",
+                            value, reg_name
+                        ))
+                    })?,
+                    None => value,
+                };
+                Ok(())
+            }
+            None => Err(ASMError::Exception(format!(
+                "register {} is not defined",
+                reg_name
+            ))),
+        }
+    }
+
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    fn ensure_unfrozen(&self) -> Result<(), ASMError> {
+        if self.frozen {
+            return Err(ASMError::Exception(
+                "definition is frozen and can no longer be modified".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    pub fn with_require_halt(mut self, require_halt: bool) -> Self {
+        self.require_halt = require_halt;
+        self
+    }
+
+    pub fn with_line_numbers(mut self, line_numbers: bool) -> Self {
+        self.line_numbers = line_numbers;
+        self
+    }
+
+    /// Resolves jumps to numbered lines through a table indexed by line number, built at the
+    /// start of each run when the line numbers are dense enough.
+    pub fn with_line_jump_table(mut self, line_jump_table: bool) -> Self {
+        self.line_jump_table = line_jump_table;
+        self
+    }
+
+    pub fn with_immediate_prefix(mut self, immediate_prefix: bool) -> Self {
+        self.immediate_prefix = immediate_prefix;
+        self
+    }
+
+    pub fn with_nested_comments(mut self, nested_comments: bool) -> Self {
+        self.nested_comments = nested_comments;
+        self
+    }
+
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = Some(max_line_length);
+        self
+    }
+
+    pub fn with_max_args_per_instruction(mut self, max_args: usize) -> Self {
+        self.max_args_per_instruction = Some(max_args);
+        self
+    }
+
+    pub fn with_implicit_registers(mut self, implicit_registers: bool) -> Self {
+        self.implicit_registers = implicit_registers;
+        self
+    }
+
+    /// Makes `scan` keep going after lexer and parse errors so one pass reports every
+    /// problem. `validate` only raises warnings, which never stop it, so the mode has no
+    /// effect there. Running a program still stops at the first fatal error.
+    pub fn with_collect_all_errors(mut self, collect_all_errors: bool) -> Self {
+        self.collect_all_errors = collect_all_errors;
+        self
+    }
+
+    pub fn with_output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_memory(mut self, cells: usize) -> Self {
+        self.memory = vec![ASMValue::new_int(0, None); cells];
+        self
+    }
+
+    /// Parses unprefixed numeric arguments in `radix`; `0x` and `0b` still override it.
+    /// Above 10 this takes letters too: any bare argument made only of digits of the
+    /// radix, such as `add` or `beef` in radix 16, is read as a number rather than as a
+    /// label or constant of that name. Local label references like `1b` stay references.
+    /// Defining a label or constant with such a name is a scan error.
+    pub fn with_default_radix(mut self, radix: u32) -> Self {
+        if !(2..=36).contains(&radix) {
+            self.raise_exception(
+                format!("{} is not a supported radix", radix).as_str(),
+                false,
+            );
+            return self;
+        }
+        self.default_radix = radix;
+        self
+    }
+
+    pub fn with_entry_point(mut self, label: &str) -> Self {
+        self.entry_point = Some(label.to_string());
+        self
+    }
+
+    pub fn with_arithmetic_mode(mut self, mode: ArithmeticMode) -> Self {
+        self.arithmetic_mode = mode;
+        self
+    }
+
+    pub fn with_register_assign_mode(mut self, mode: ArithmeticMode) -> Self {
+        self.register_assign_mode = Some(mode);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng_state = if seed == 0 { DEFAULT_SEED } else { seed };
+        self
+    }
+
+    pub fn with_bare_registers(mut self, bare_registers: bool) -> Self {
+        self.bare_registers = bare_registers;
+        self
+    }
+
+    pub fn with_register_sigil(mut self, syntax: RegisterSyntax) -> Self {
+        self.register_syntax = syntax;
         self
     }
 
@@ -65,174 +577,1763 @@ impl ASMDefinition {
         instruction_name: &str,
         closure: fn(&mut ASMDefinition, Vec<ASMValue>),
     ) -> Self {
-        self.instructions.insert(
-            instruction_name.into(),
-            ASMInstruction::new(closure, self.ptr_to_self.unwrap().clone()),
-        );
+        let instruction = ASMInstruction::new(closure, self.ptr_to_self.unwrap());
+        self.add_instruction(instruction_name, instruction);
         self
     }
 
-    pub fn raise_exception(&mut self, error_message: &str, halt_execution: bool) {
-        println!("{}", error_message);
-        if halt_execution {
-            self.halted = true;
+    fn add_instruction(&mut self, instruction_name: &str, instruction: ASMInstruction) {
+        if let Err(error) = self.ensure_unfrozen() {
+            self.raise_error(error, false);
+            return;
         }
-        self.errors += 1;
+        let id = self.symbols.intern(instruction_name);
+        self.instructions.insert(id, instruction);
     }
 
-    pub fn run(&mut self, token_stream: Vec<ASTNode>) {
-        let mut current_instruction: Option<ASMInstruction> = None;
-        let mut current_args: Vec<ASMValue> = Vec::new();
-        while self.current_line < token_stream.len() {
-            let token: ASTNode = token_stream[self.current_line].clone();
-            if self.halted {
-                return;
-            }
-            match token {
-                ASTNode::ASTValue(value) => {
-                    if current_instruction.is_none() {
-                        self.raise_exception(
-                            "ASTValue encountered with no instruction present",
-                            true,
-                        );
-                        continue;
-                    }
-                    current_args.push(value.clone());
-                }
-                ASTNode::ASTInstruction(instruction) => {
-                    if current_instruction.is_some() {
-                        self.raise_exception(
-                            "ASTInstruction encountered when another instruction is called",
-                            true,
-                        );
-                        continue;
-                    }
-                    let instruction_ref = match self.instructions.get(&instruction) {
-                        Some(reference) => reference,
-                        None => {
-                            self.raise_exception("Not a valid instruction", true);
-                            continue;
-                        }
-                    };
-                    current_instruction = Some((*instruction_ref).clone());
-                }
-                ASTNode::ASTRegister(reference) => {
-                    if current_instruction.is_none() {
-                        self.raise_exception(
-                            "Register reference encountered with no instruction present",
-                            false,
-                        );
-                        continue;
-                    }
-                    let register_ref = match self.registers.get(&reference) {
-                        Some(_) => ASMValue::new_reg(reference.clone(), self.ptr_to_self.clone()),
-                        None => {
-                            self.raise_exception("Register not defined in ASMDefinition", true);
-                            continue;
-                        }
-                    };
-
-                    current_args.push(register_ref);
-                }
-                ASTNode::ASTExprEnd => match current_instruction {
-                    Some(instruction) => {
-                        instruction.call(self, current_args.clone());
-                        current_instruction = None;
-                        current_args.clear();
-                    }
-                    None => {}
-                },
-            }
-            self.current_line += 1;
+    pub fn remove_instruction(&mut self, instruction_name: &str) -> Result<(), ASMError> {
+        self.ensure_unfrozen()?;
+        let removed = self
+            .symbol(instruction_name)
+            .and_then(|id| self.instructions.remove(&id));
+        match removed {
+            Some(_) => Ok(()),
+            None => Err(ASMError::Exception(format!(
+                "{} is an unknown instruction",
+                instruction_name
+            ))),
         }
     }
 
-    pub fn jump_to_value(&mut self, value: ASMValue) {
-        if let ASMValueHolder::Int(inner_value) = value.get_value_holder() {
-            self.jump(inner_value as usize);
-            return;
+    pub fn get_register(&self, reg_name: &str) -> Option<ASMValue> {
+        self.registers.get(reg_name).cloned()
+    }
+
+    pub fn has_register(&self, reg_name: &str) -> bool {
+        self.registers.contains_key(reg_name)
+    }
+
+    pub fn has_instruction(&self, name: &str) -> bool {
+        self.classify_instruction(name).is_some()
+    }
+
+    pub fn memory(&self) -> &[ASMValue] {
+        &self.memory
+    }
+
+    pub fn read_memory(&self, address: usize) -> Result<ASMValue, ASMError> {
+        match self.memory.get(address) {
+            Some(value) => Ok(value.clone()),
+            None => Err(ASMError::Exception(format!(
+                "memory address {} is out of bounds",
+                address
+            ))),
         }
-        self.raise_exception("Invalid value of provided destination!", true);
     }
 
-    pub fn jump_to_label(&mut self, label: ASMValue) {
-        if let ASMValueHolder::Label(label_string) = label.get_value_holder() {
-            if self.labels.contains_key(&label_string) {
-                self.jump(*self.labels.get(&label_string).unwrap());
-                return;
+    pub fn write_memory(&mut self, address: usize, value: ASMValue) -> Result<(), ASMError> {
+        match self.memory.get_mut(address) {
+            Some(cell) => {
+                *cell = value;
+                Ok(())
             }
+            None => Err(ASMError::Exception(format!(
+                "memory address {} is out of bounds",
+                address
+            ))),
         }
-        self.raise_exception("Invalid label provided!", true);
     }
 
-    pub fn jump(&mut self, destination: usize) {
-        self.current_line = destination - 1;
+    pub fn dump_memory(&self, start: usize, length: usize) -> Vec<ASMValue> {
+        let start = start.min(self.memory.len());
+        let end = start.saturating_add(length).min(self.memory.len());
+        self.memory[start..end].to_vec()
     }
 
-    pub fn scan(&mut self, code: String) -> Vec<ASTNode> {
-        let mut output: Vec<ASTNode> = Vec::new();
-        let lines: Vec<String> = code.split("\n").map(|x| x.to_string()).collect();
+    pub fn fill_memory(
+        &mut self,
+        start: usize,
+        length: usize,
+        value: ASMValue,
+    ) -> Result<(), ASMError> {
+        let end = match start.checked_add(length) {
+            Some(end) if end <= self.memory.len() => end,
+            _ => {
+                return Err(ASMError::Exception(format!(
+                    "memory range {}..{} is out of bounds",
+                    start,
+                    start.saturating_add(length)
+                )))
+            }
+        };
+        for cell in self.memory[start..end].iter_mut() {
+            *cell = value.clone();
+        }
+        Ok(())
+    }
 
-        for line in lines.iter() {
-            let mut usable_line: String = line.clone();
-            if usable_line.find(";;").is_some() {
-                usable_line = usable_line.split_once(";;").unwrap().0.into();
+    pub fn copy_memory(
+        &mut self,
+        destination: usize,
+        source: usize,
+        length: usize,
+    ) -> Result<(), ASMError> {
+        for (start, name) in [(source, "source"), (destination, "destination")] {
+            if start
+                .checked_add(length)
+                .is_none_or(|end| end > self.memory.len())
+            {
+                return Err(ASMError::Exception(format!(
+                    "memory {} range {}..{} is out of bounds",
+                    name,
+                    start,
+                    start.saturating_add(length)
+                )));
             }
-            usable_line = usable_line.trim_end().into();
-            if usable_line.len() == 0 {
-                continue;
+        }
+        if destination > source {
+            for offset in (0..length).rev() {
+                self.memory[destination + offset] = self.memory[source + offset].clone();
             }
-            let mut words: Vec<String> = usable_line.split(" ").map(|x| x.to_string()).collect();
-            if words[0].chars().last().unwrap() == ':' {
-                let mut label = words[0].clone();
-                label.retain(|c| c != ':');
-                self.labels.insert(label, output.len());
-                continue;
+        } else {
+            for offset in 0..length {
+                self.memory[destination + offset] = self.memory[source + offset].clone();
             }
-            output.push(self.match_instruction(words[0].clone()));
-            words.remove(0);
-            words
-                .iter()
-                .for_each(|word| output.push(self.match_argument(word.clone())));
-            output.push(ASTNode::ASTExprEnd);
         }
-        output
+        Ok(())
     }
 
-    fn match_instruction(&mut self, mut word: String) -> ASTNode {
-        word.retain(|c| !c.is_whitespace());
-        if !self.instructions.contains_key(&word) {
-            self.raise_exception(format!("{} is an unknown instruction", word).as_str(), true);
+    pub fn resolve_arg(&self, arg: &ASMValue) -> Result<ASMValueHolder, ASMError> {
+        match arg.get_value_holder() {
+            ASMValueHolder::Register(name) => match self.registers.get(&name) {
+                Some(value) => Ok(value.get_value_holder()),
+                None => Err(ASMError::Exception(format!(
+                    "register {} is not defined",
+                    name
+                ))),
+            },
+            holder => Ok(holder),
         }
-        ASTNode::ASTInstruction(word)
     }
 
-    fn match_argument(&mut self, mut word: String) -> ASTNode {
-        word.retain(|c| !c.is_whitespace());
-        if word.len() == 0 {
-            self.raise_exception("Empty argument!", true);
-            return ASTNode::ASTExprEnd;
-        }
-        if word.chars().all(|c| c.is_numeric()) {
-            return ASTNode::ASTValue(word.parse::<i32>().unwrap().into());
-        }
-        if word.chars().all(|c| c.is_alphanumeric()) {
-            return ASTNode::ASTValue(ASMValue::new_label(word, None));
-        }
-        if word.chars().next().unwrap() == '[' && word.chars().last().unwrap() == ']' {
-            word.retain(|c| c.is_alphanumeric());
-            println!("{}", word);
-            return ASTNode::ASTRegister(word);
+    pub fn error_count(&self) -> u64 {
+        self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ASMError> {
+        self.errors = 0;
+        std::mem::take(&mut self.raised_errors)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            flag: Arc::clone(&self.should_stop),
         }
-        if word.chars().last().unwrap() == '"' && word.chars().next().unwrap() == '"' {
-            todo!()
+    }
+
+    fn stop_requested(&mut self) -> bool {
+        if !self.should_stop.swap(false, atomic::Ordering::SeqCst) {
+            return false;
         }
+        self.cancelled = true;
+        self.halt();
+        true
+    }
 
-        ASTNode::ASTExprEnd
+    pub fn insert_stdlib(self) -> Self {
+        asm_stdlib::insert_stdlib(self)
     }
 
-    pub fn interpret(&mut self, code: String) {
-        let ast = self.scan(code);
-        self.run(ast);
+    pub fn insert_closure(mut self, instruction_name: &str, closure: ASMClosure) -> Self {
+        let instruction = ASMInstruction::new_closure(closure, self.ptr_to_self.unwrap());
+        self.add_instruction(instruction_name, instruction);
+        self
+    }
+
+    pub fn insert_instruction_with_arity(
+        mut self,
+        instruction_name: &str,
+        arity: usize,
+        closure: fn(&mut ASMDefinition, Vec<ASMValue>),
+    ) -> Self {
+        let instruction = ASMInstruction::new(closure, self.ptr_to_self.unwrap()).with_arity(arity);
+        self.add_instruction(instruction_name, instruction);
+        self
+    }
+
+    pub fn insert_raw_instruction(
+        mut self,
+        instruction_name: &str,
+        closure: fn(&mut ASMDefinition, Vec<ASMValue>),
+    ) -> Self {
+        let instruction =
+            ASMInstruction::new(closure, self.ptr_to_self.unwrap()).with_raw_operands();
+        self.add_instruction(instruction_name, instruction);
+        self
+    }
+
+    pub fn is_raw_instruction(&self, name: &str) -> bool {
+        self.classify_instruction(name)
+            .and_then(|id| self.instructions.get(&id))
+            .is_some_and(|instruction| instruction.takes_raw_operands())
+    }
+
+    pub fn insert_instruction_doc(
+        mut self,
+        instruction_name: &str,
+        closure: fn(&mut ASMDefinition, Vec<ASMValue>),
+        description: &str,
+        usage: &str,
+    ) -> Self {
+        let instruction =
+            ASMInstruction::new(closure, self.ptr_to_self.unwrap()).with_doc(description, usage);
+        self.add_instruction(instruction_name, instruction);
+        self
+    }
+
+    pub fn instruction_doc(&self, name: &str) -> Option<&InstructionDoc> {
+        self.classify_instruction(name)
+            .and_then(|id| self.instructions.get(&id))
+            .and_then(|instruction| instruction.get_doc())
+    }
+
+    pub fn insert_instruction_sig(
+        mut self,
+        instruction_name: &str,
+        signature: &[ArgKind],
+        closure: fn(&mut ASMDefinition, Vec<ASMValue>),
+    ) -> Self {
+        let instruction =
+            ASMInstruction::new(closure, self.ptr_to_self.unwrap()).with_signature(signature);
+        self.add_instruction(instruction_name, instruction);
+        self
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name)
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        self.symbols.intern(name)
+    }
+
+    pub fn symbol_name(&self, id: Symbol) -> &str {
+        self.symbols.name(id)
+    }
+
+    pub fn symbol_table(&self) -> SymbolTable {
+        SymbolTable::new(self)
+    }
+
+    pub fn listing(&self, stream: &[ASTNode]) -> Vec<ListingLine> {
+        asm_listing::listing(self, stream)
+    }
+
+    /// Addresses of the instructions reachable from the entry point, following the
+    /// control flow declared with `with_control_flow`.
+    pub fn reachable_lines(&self, stream: &[ASTNode]) -> HashSet<usize> {
+        asm_reachability::reachable_lines(self, stream)
+    }
+
+    pub fn ast_debug(&self, stream: &[ASTNode]) -> String {
+        asm_ast_debug::ast_debug(self, stream)
+    }
+
+    pub fn register_syntax(&self) -> RegisterSyntax {
+        self.register_syntax
+    }
+
+    pub fn uses_line_numbers(&self) -> bool {
+        self.line_numbers
+    }
+
+    pub fn uses_immediate_prefix(&self) -> bool {
+        self.immediate_prefix
+    }
+
+    pub fn uses_nested_comments(&self) -> bool {
+        self.nested_comments
+    }
+
+    pub fn max_line_length(&self) -> Option<usize> {
+        self.max_line_length
+    }
+
+    pub fn entry_point(&self) -> Option<&str> {
+        self.entry_point.as_deref()
+    }
+
+    pub fn uses_implicit_registers(&self) -> bool {
+        self.implicit_registers
+    }
+
+    pub(crate) fn ensure_register(&mut self, reg_name: &str) -> bool {
+        if self.registers.contains_key(reg_name) {
+            return true;
+        }
+        if !self.implicit_registers {
+            return false;
+        }
+        let default = ASMValue::new_int(0, self.ptr_to_self);
+        self.registers.insert(reg_name.into(), default.clone());
+        self.register_defaults.insert(reg_name.into(), default);
+        true
+    }
+
+    pub fn default_radix(&self) -> u32 {
+        self.default_radix
+    }
+
+    pub fn arithmetic_mode(&self) -> ArithmeticMode {
+        self.arithmetic_mode
+    }
+
+    pub fn raise_exception(&mut self, error_message: &str, halt_execution: bool) {
+        self.raise_error(ASMError::Exception(error_message.into()), halt_execution);
+    }
+
+    pub fn raise_error(&mut self, error: ASMError, halt_execution: bool) {
+        println!("{}", error);
+        if halt_execution && !self.linting {
+            self.halted = true;
+        }
+        self.errors += 1;
+        self.raised_errors.push(error);
+    }
+
+    pub fn raise_warning(&mut self, warning_message: &str) {
+        println!("Warning: {}", warning_message);
+        self.warnings
+            .push(ASMError::Exception(warning_message.into()));
+    }
+
+    pub fn write_output(&mut self, text: &str) {
+        if self.output.write_all(text.as_bytes()).is_err() {
+            self.raise_exception("Failed to write program output", true);
+        }
+    }
+
+    pub fn warnings(&self) -> &[ASMError] {
+        &self.warnings
+    }
+
+    pub fn reset(&mut self) {
+        self.reset_run_state();
+        self.errors = 0;
+        self.raised_errors.clear();
+        self.warnings.clear();
+    }
+
+    fn reset_run_state(&mut self) {
+        let banks = std::iter::once(&mut self.registers).chain(self.register_banks.values_mut());
+        for bank in banks {
+            for (name, value) in bank.iter_mut() {
+                *value = match self.register_defaults.get(name) {
+                    Some(default) => default.clone(),
+                    None => ASMValue::new_int(0, self.ptr_to_self),
+                };
+            }
+        }
+        for cell in self.memory.iter_mut() {
+            *cell = ASMValue::new_int(0, None);
+        }
+        self.labels.clear();
+        self.local_labels.clear();
+        self.constants.clear();
+        self.cycles = 0;
+        self.call_stack.clear();
+        self.comparison = None;
+        self.coverage.clear();
+        self.current_line = 0;
+        self.pending_jump = None;
+        self.halted = false;
+        self.exit_code = None;
+        self.stepping = false;
+    }
+
+    pub fn run(&mut self, token_stream: Vec<ASTNode>) -> CompletionReason {
+        let mark = match self.begin_run(instruction_starts(&token_stream)) {
+            Some(mark) => mark,
+            None => return CompletionReason::Error,
+        };
+        if !self.enter_program() {
+            return self.finish_run(mark);
+        }
+        let mut expression = PendingExpression::new();
+        let mut clock = RunClock::new();
+        while self.current_line < token_stream.len() && !self.halted {
+            if self.stop_requested() || self.time_limit_exceeded(&mut clock) {
+                break;
+            }
+            let token: ASTNode = token_stream[self.current_line].clone();
+            self.execute_token(token, &mut expression);
+            if self.halted {
+                break;
+            }
+            self.advance();
+        }
+        self.finish_run(mark)
+    }
+
+    /// Runs the next instruction of `token_stream`. The first step of a program starts at
+    /// the entry point; later ones carry on from the current line until a step reports
+    /// that nothing is left to run. The stream's instruction starts and line table are
+    /// built on the first step and reused by the later ones, so keep passing the same
+    /// stream until the program ends or the run state is reset.
+    pub fn step(&mut self, token_stream: &[ASTNode]) -> bool {
+        let started = match self.stepping {
+            true => self.claim_run(),
+            false => self.begin_run(instruction_starts(token_stream)),
+        };
+        if started.is_none() {
+            return false;
+        }
+        if !self.stepping {
+            self.stepping = true;
+            if !self.enter_program() {
+                self.stepping = false;
+                self.running = false;
+                return false;
+            }
+        }
+        let mut expression = PendingExpression::new();
+        while self.current_line < token_stream.len() && !self.halted {
+            let token: ASTNode = token_stream[self.current_line].clone();
+            let expression_end = matches!(token, ASTNode::ASTExprEnd);
+            self.execute_token(token, &mut expression);
+            if self.halted {
+                break;
+            }
+            self.advance();
+            if expression_end {
+                break;
+            }
+        }
+        self.running = false;
+        self.stepping = self.current_line < token_stream.len() && !self.halted;
+        self.stepping
+    }
+
+    pub fn run_until(
+        &mut self,
+        token_stream: &[ASTNode],
+        mut predicate: impl FnMut(&ASMDefinition) -> bool,
+    ) -> StepResult {
+        loop {
+            let more = self.step(token_stream);
+            if predicate(self) {
+                return StepResult::ConditionMet;
+            }
+            if !more {
+                return if self.halted {
+                    StepResult::Halted
+                } else {
+                    StepResult::ReachedEnd
+                };
+            }
+        }
+    }
+
+    pub fn coverage(&self) -> &HashSet<usize> {
+        &self.coverage
+    }
+
+    pub fn coverage_percent(&self, token_stream: &[ASTNode]) -> f64 {
+        let starts = instruction_starts(token_stream);
+        if starts.is_empty() {
+            return 100.0;
+        }
+        let covered = starts
+            .iter()
+            .filter(|start| self.coverage.contains(start))
+            .count();
+        covered as f64 * 100.0 / starts.len() as f64
+    }
+
+    pub fn registers_snapshot(&self) -> HashMap<String, ASMValue> {
+        self.registers.clone()
+    }
+
+    pub fn run_compiled(&mut self, program: &CompiledProgram) -> CompletionReason {
+        self.labels = program.labels.clone();
+        let mark = match self.begin_run((0..program.opcodes.len()).collect()) {
+            Some(mark) => mark,
+            None => return CompletionReason::Error,
+        };
+        if !program.is_bound() {
+            self.raise_exception("compiled program is not bound to a definition", true);
+            return self.finish_run(mark);
+        }
+        if !self.enter_program() {
+            return self.finish_run(mark);
+        }
+        let mut clock = RunClock::new();
+        while self.current_line < program.opcodes.len() && !self.halted {
+            if self.stop_requested() || self.time_limit_exceeded(&mut clock) {
+                break;
+            }
+            let opcode = &program.opcodes[self.current_line];
+            let mut args: Vec<ASMValue> = Vec::with_capacity(opcode.args.len());
+            for operand in opcode.args.iter() {
+                let value = match operand {
+                    Operand::Value(value) => Some(value.clone()),
+                    Operand::Register(name) => {
+                        Some(ASMValue::new_reg(name.clone(), self.ptr_to_self))
+                    }
+                    Operand::Indexed(base, index) => {
+                        let base = match base.as_ref() {
+                            Operand::Register(name) => self.registers.get(name).cloned(),
+                            Operand::Value(value) => Some(value.clone()),
+                            Operand::Indexed(_, _) => None,
+                        };
+                        self.index_value(base, *index)
+                    }
+                };
+                match value {
+                    Some(value) => args.push(value),
+                    None => break,
+                }
+            }
+            if args.len() < opcode.args.len() {
+                self.advance();
+                continue;
+            }
+            self.call_instruction(
+                &program.instructions[opcode.instruction],
+                program.names[opcode.instruction],
+                args,
+            );
+            if self.halted {
+                break;
+            }
+            self.advance();
+        }
+        self.finish_run(mark)
+    }
+
+    /// Compiles a scanned stream into opcodes. Only registered instructions compile;
+    /// mnemonics left to the unknown instruction handler are rejected as unknown.
+    pub fn compile(&mut self, stream: &[ASTNode]) -> CompiledProgram {
+        asm_compiled::compile(self, stream)
+    }
+
+    fn begin_run(&mut self, instruction_starts: Vec<usize>) -> Option<usize> {
+        let mark = self.claim_run()?;
+        self.instruction_starts = instruction_starts;
+        self.line_table = match self.line_jump_table {
+            true => LineTable::build(&self.labels),
+            false => LineTable::default(),
+        };
+        Some(mark)
+    }
+
+    /// Marks the definition as running without rebuilding the per-stream tables, for
+    /// steps after the first one of a stream.
+    fn claim_run(&mut self) -> Option<usize> {
+        if self.running {
+            self.raise_exception("run called while the definition is already running", true);
+            return None;
+        }
+        self.running = true;
+        let self_ptr: *mut ASMDefinition = self;
+        self.ptr_to_self = Some(self_ptr);
+        self.pending_jump = None;
+        self.cancelled = false;
+        Some(self.error_mark())
+    }
+
+    fn enter_program(&mut self) -> bool {
+        let label = match &self.entry_point {
+            Some(label) => label.clone(),
+            None => return true,
+        };
+        match self.labels.get(&label) {
+            Some(line) => {
+                self.current_line = *line;
+                true
+            }
+            None => {
+                self.raise_exception(
+                    format!("entry point {} is not a defined label", label).as_str(),
+                    true,
+                );
+                false
+            }
+        }
+    }
+
+    fn time_limit_exceeded(&mut self, clock: &mut RunClock) -> bool {
+        clock.steps += 1;
+        if let Some(limit) = self.time_limit {
+            if clock.steps.is_multiple_of(TIME_LIMIT_CHECK_INTERVAL)
+                && clock.started.elapsed() > limit
+            {
+                self.raise_exception("Time limit exceeded!", true);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn finish_run(&mut self, mark: usize) -> CompletionReason {
+        self.running = false;
+        if !self.halted && self.require_halt {
+            self.raise_exception("Program ended without an explicit halt", true);
+        }
+        let reason = if self.raised_errors.len() > mark {
+            CompletionReason::Error
+        } else if self.cancelled {
+            CompletionReason::Cancelled
+        } else if self.halted {
+            CompletionReason::Halted
+        } else {
+            CompletionReason::ReachedEnd
+        };
+        let errors = self.errors;
+        if let Some(hook) = self.halt_hook.as_mut() {
+            hook(errors, reason);
+        }
+        reason
+    }
+
+    pub fn set_halt_hook(&mut self, hook: HaltHook) {
+        self.halt_hook = Some(hook);
+    }
+
+    pub fn set_literal_parser(&mut self, parser: LiteralParser) {
+        self.literal_parser = Some(parser);
+    }
+
+    pub(crate) fn parse_custom_literal(&self, word: &str) -> Option<ASMValueHolder> {
+        self.literal_parser
+            .as_ref()
+            .and_then(|parser| parser(word))
+            .map(|value| value.get_value_holder())
+    }
+
+    pub fn set_instruction_cost(&mut self, name: &str, cycles: u64) {
+        let id = self.symbols.intern(name);
+        self.instruction_costs.insert(id, cycles);
+    }
+
+    /// Declares how an instruction moves the program counter. Instructions without a
+    /// declaration are assumed to continue at the next instruction.
+    pub fn with_control_flow(mut self, name: &str, kind: ControlFlow) -> Self {
+        let id = self.symbols.intern(name);
+        self.control_flow.insert(id, kind);
+        self
+    }
+
+    pub fn control_flow(&self, id: Symbol) -> Option<ControlFlow> {
+        self.control_flow.get(&id).copied()
+    }
+
+    pub fn cycles_elapsed(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn on_instruction(&mut self, name: &str, hook: InstructionHook) {
+        let id = self.symbols.intern(name);
+        self.instruction_hooks.insert(id, hook);
+    }
+
+    /// Installs a hook that may rewrite the arguments of every instruction before its
+    /// arity and signature are checked and its closure runs.
+    pub fn set_arg_rewrite_hook(&mut self, hook: ArgRewriteHook) {
+        self.arg_rewrite_hook = Some(hook);
+    }
+
+    /// Installs a handler that runs instructions with no registered closure instead of
+    /// rejecting them. It receives the mnemonic and the evaluated arguments, after the
+    /// argument rewrite hook, instruction cost and `on_instruction` hook have applied as
+    /// they would for a registered instruction. The handler is not used by `compile`.
+    pub fn set_unknown_instruction_handler(&mut self, handler: UnknownInstructionHandler) {
+        self.unknown_instruction_handler = Some(handler);
+    }
+
+    pub fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    pub fn exit(&mut self, code: i32) {
+        self.exit_code = Some(code);
+        self.halt();
+    }
+
+    fn execute_token(&mut self, token: ASTNode, expression: &mut PendingExpression) {
+        match token {
+            ASTNode::ASTValue(value) => {
+                if !expression.is_open() {
+                    self.raise_exception("ASTValue encountered with no instruction present", true);
+                    return;
+                }
+                expression.args.push(value);
+            }
+            ASTNode::ASTPooled(_, value) => {
+                if !expression.is_open() {
+                    self.raise_exception("ASTValue encountered with no instruction present", true);
+                    return;
+                }
+                expression.args.push((*value).clone());
+            }
+            ASTNode::ASTInstruction(instruction) => {
+                if expression.is_open() {
+                    self.raise_exception(
+                        "ASTInstruction encountered when another instruction is called",
+                        true,
+                    );
+                    return;
+                }
+                let instruction_ref = match self.instructions.get(&instruction) {
+                    Some(reference) => reference,
+                    None if self.unknown_instruction_handler.is_some() => {
+                        expression.unknown = true;
+                        expression.name = instruction;
+                        return;
+                    }
+                    None => {
+                        self.raise_exception("Not a valid instruction", true);
+                        return;
+                    }
+                };
+                expression.instruction = Some((*instruction_ref).clone());
+                expression.name = instruction;
+            }
+            ASTNode::ASTRegister(reference) => {
+                if !expression.is_open() {
+                    self.raise_exception(
+                        "Register reference encountered with no instruction present",
+                        false,
+                    );
+                    return;
+                }
+                if !self.ensure_register(&reference) {
+                    let message = match self.source_line(self.current_line) {
+                        Some(line) => {
+                            format!("line {}: register '{}' not defined", line + 1, reference)
+                        }
+                        None => format!("register '{}' not defined", reference),
+                    };
+                    self.raise_exception(message.as_str(), true);
+                    return;
+                }
+                expression
+                    .args
+                    .push(ASMValue::new_reg(reference, self.ptr_to_self));
+            }
+            ASTNode::ASTIndexed(base, index) => {
+                if !expression.is_open() {
+                    self.raise_exception("Index encountered with no instruction present", true);
+                    return;
+                }
+                let base = match *base {
+                    ASTNode::ASTValue(value) => Some(value),
+                    ASTNode::ASTRegister(reference) => self.registers.get(&reference).cloned(),
+                    _ => None,
+                };
+                if let Some(element) = self.index_value(base, index) {
+                    expression.args.push(element);
+                }
+            }
+            ASTNode::ASTExprEnd => match expression.instruction.take() {
+                Some(instruction) => {
+                    let args = std::mem::take(&mut expression.args);
+                    self.call_instruction(&instruction, expression.name, args);
+                }
+                None if expression.unknown => {
+                    expression.unknown = false;
+                    let args = std::mem::take(&mut expression.args);
+                    self.call_unknown_instruction(expression.name, args);
+                }
+                None => {
+                    let message = format!(
+                        "Expression end at {} encountered with no instruction present",
+                        self.current_line
+                    );
+                    if self.collect_all_errors {
+                        self.raise_warning(message.as_str());
+                    } else {
+                        self.raise_exception(message.as_str(), true);
+                    }
+                }
+            },
+        }
+    }
+
+    fn index_value(&mut self, base: Option<ASMValue>, index: usize) -> Option<ASMValue> {
+        let base = match base {
+            Some(base) => base,
+            None => {
+                self.raise_exception("Indexed value is not defined", true);
+                return None;
+            }
+        };
+        match base.element(index) {
+            Ok(element) => Some(element),
+            Err(error) => {
+                self.raise_exception(error, true);
+                None
+            }
+        }
+    }
+
+    fn call_instruction(
+        &mut self,
+        instruction: &ASMInstruction,
+        name: Symbol,
+        mut args: Vec<ASMValue>,
+    ) {
+        if let Some(hook) = self.arg_rewrite_hook.as_mut() {
+            hook(self.symbols.name(name), &mut args);
+        }
+        match instruction.get_arity() {
+            Some(arity) if arity != args.len() => self.raise_exception(
+                format!(
+                    "{} expects {} arguments, got {}",
+                    self.symbol_name(name),
+                    arity,
+                    args.len()
+                )
+                .as_str(),
+                true,
+            ),
+            _ => {
+                if let Err(error) = self.check_signature(instruction, name, &args) {
+                    self.raise_error(error, true);
+                    return;
+                }
+                self.cycles += self.instruction_costs.get(&name).copied().unwrap_or(1);
+                self.coverage.insert(self.instruction_address());
+                if let Some(hook) = self.instruction_hooks.get_mut(&name) {
+                    hook(&args);
+                }
+                instruction.call(self, args)
+            }
+        }
+    }
+
+    fn call_unknown_instruction(&mut self, name: Symbol, mut args: Vec<ASMValue>) {
+        let mut handler = match self.unknown_instruction_handler.take() {
+            Some(handler) => handler,
+            None => {
+                self.raise_exception("Not a valid instruction", true);
+                return;
+            }
+        };
+        if let Some(hook) = self.arg_rewrite_hook.as_mut() {
+            hook(self.symbols.name(name), &mut args);
+        }
+        self.cycles += self.instruction_costs.get(&name).copied().unwrap_or(1);
+        self.coverage.insert(self.instruction_address());
+        if let Some(hook) = self.instruction_hooks.get_mut(&name) {
+            hook(&args);
+        }
+        let name = self.symbol_name(name).to_string();
+        handler(self, &name, args);
+        if self.unknown_instruction_handler.is_none() {
+            self.unknown_instruction_handler = Some(handler);
+        }
+    }
+
+    fn check_signature(
+        &self,
+        instruction: &ASMInstruction,
+        name: Symbol,
+        args: &[ASMValue],
+    ) -> Result<(), ASMError> {
+        let signature = match instruction.get_signature() {
+            Some(signature) => signature,
+            None => return Ok(()),
+        };
+        for (position, (kind, arg)) in signature.iter().zip(args.iter()).enumerate() {
+            let resolved = self.resolve_arg(arg)?;
+            if !kind.accepts(arg, &resolved) {
+                let actual = match arg.get_value_holder() {
+                    ASMValueHolder::Register(register) => format!("register {}", register),
+                    _ => format!("{:?}", resolved),
+                };
+                return Err(ASMError::ArgumentMismatch {
+                    instruction: self.symbol_name(name).to_string(),
+                    position,
+                    expected: *kind,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self) {
+        self.current_line = match self.pending_jump.take() {
+            Some(destination) => destination,
+            None => self.current_line + 1,
+        };
+    }
+
+    pub fn jump_to_value(&mut self, value: ASMValue) {
+        let destination = match value.get_value_holder() {
+            ASMValueHolder::Register(_) => value.try_resolve_register().ok(),
+            _ => Some(value),
+        };
+        if let Some(ASMValueHolder::Int(inner_value)) = destination.map(|v| v.get_value_holder()) {
+            if inner_value >= 0 {
+                self.jump(inner_value as usize);
+                return;
+            }
+        }
+        self.raise_exception("Invalid value of provided destination!", true);
+    }
+
+    pub fn jump_to_label(&mut self, label: ASMValue) {
+        match label.get_value_holder() {
+            ASMValueHolder::Label(label_string)
+                if self.jump_to_label_name(&label_string).is_ok() =>
+            {
+                return;
+            }
+            ASMValueHolder::ResolvedLabel(_, destination) => {
+                self.jump(destination);
+                return;
+            }
+            _ => {}
+        }
+        self.raise_exception("Invalid label provided!", true);
+    }
+
+    pub fn insert_label(&mut self, name: &str, line: usize) -> Result<(), ASMError> {
+        self.ensure_unfrozen()?;
+        if self.labels.contains_key(name) {
+            return Err(ASMError::Exception(format!(
+                "label {} is defined twice",
+                name
+            )));
+        }
+        if line > self.program_length {
+            return Err(ASMError::Exception(format!(
+                "label {} points past the end of the program",
+                name
+            )));
+        }
+        self.labels.insert(name.to_string(), line);
+        Ok(())
+    }
+
+    pub fn jump_to_label_name(&mut self, name: &str) -> Result<(), ASMError> {
+        match self.labels.get(name) {
+            Some(destination) => {
+                self.jump(*destination);
+                Ok(())
+            }
+            None => Err(ASMError::Exception(format!(
+                "label {} is not defined",
+                name
+            ))),
+        }
+    }
+
+    pub fn jump_to_line(&mut self, line_number: i32) -> Result<(), ASMError> {
+        match self.line_table.get(line_number as i64) {
+            Some(destination) => {
+                self.jump(destination);
+                Ok(())
+            }
+            None => self.jump_to_label_name(&line_number.to_string()),
+        }
+    }
+
+    pub fn instruction_address(&self) -> usize {
+        self.instruction_starts
+            .iter()
+            .rev()
+            .find(|start| **start <= self.current_line)
+            .copied()
+            .unwrap_or(self.current_line)
+    }
+
+    pub fn jump_relative(&mut self, offset: i32) {
+        let current_instruction = self
+            .instruction_starts
+            .iter()
+            .rposition(|start| *start <= self.current_line);
+        let target = current_instruction
+            .and_then(|index| (index as i64).checked_add(offset as i64))
+            .filter(|target| *target >= 0 && (*target as usize) < self.instruction_starts.len());
+        match target {
+            Some(target) => self.jump(self.instruction_starts[target as usize]),
+            None => self.raise_exception("Relative jump out of range!", true),
+        }
+    }
+
+    pub fn call_label(&mut self, label: ASMValue) {
+        self.call_stack.push(self.current_line + 1);
+        self.jump_to_label(label);
+    }
+
+    pub fn return_from_call(&mut self) {
+        match self.call_stack.pop() {
+            Some(return_address) => self.jump(return_address),
+            None => self.raise_exception("ret with an empty call stack", true),
+        }
+    }
+
+    pub fn set_comparison(&mut self, comparison: Ordering) {
+        self.comparison = Some(comparison);
+    }
+
+    pub fn comparison(&self) -> Option<Ordering> {
+        self.comparison
+    }
+
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack
+    }
+
+    pub fn backtrace(&self) -> Vec<String> {
+        self.call_stack
+            .iter()
+            .rev()
+            .map(|address| {
+                let label = self
+                    .labels
+                    .iter()
+                    .filter(|(_, line)| *line <= address)
+                    .max_by(|left, right| left.1.cmp(right.1).then(right.0.cmp(left.0)));
+                match label {
+                    Some((name, line)) => format!("{}+{}", name, address - line),
+                    None => address.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn jump(&mut self, destination: usize) {
+        self.pending_jump = Some(destination);
+    }
+
+    pub fn lex(&self, code: &str) -> Result<Vec<Token>, Vec<ASMError>> {
+        asm_lexer::lex(self, code)
+    }
+
+    pub fn scan(&mut self, code: String) -> Result<Vec<ASTNode>, Vec<ASMError>> {
+        self.linting = self.collect_all_errors;
+        let result = self.scan_tokens(code);
+        self.linting = false;
+        result
+    }
+
+    /// Scans another file onto `appended_program`. If it fails, the labels and constants
+    /// it defined are removed again and the earlier files are left as they were.
+    pub fn scan_append(&mut self, code: String) -> Result<(), Vec<ASMError>> {
+        let labels = self.labels.clone();
+        let constants = self.constants.clone();
+        self.scan_base = self.appended_program.len();
+        let result = self.scan(code);
+        self.scan_base = 0;
+        let stream = match result {
+            Ok(stream) => stream,
+            Err(errors) => {
+                self.labels = labels;
+                self.constants = constants;
+                return Err(errors);
+            }
+        };
+        self.appended_program.extend(stream);
+        self.program_length = self.appended_program.len();
+        Ok(())
+    }
+
+    pub fn appended_program(&self) -> &[ASTNode] {
+        &self.appended_program
+    }
+
+    pub fn source_line(&self, index: usize) -> Option<usize> {
+        self.source_lines.get(index).copied()
+    }
+
+    /// Scans source from a reader, lexing it line by line instead of reading it into a
+    /// single string first.
+    pub fn scan_reader(&mut self, reader: impl BufRead) -> Result<Vec<ASTNode>, Vec<ASMError>> {
+        self.linting = self.collect_all_errors;
+        self.begin_scan();
+        let lexed = asm_lexer::lex_reader(self, reader);
+        let result = self.scan_lexed(lexed);
+        self.linting = false;
+        result
+    }
+
+    fn begin_scan(&mut self) {
+        self.source_lines.truncate(self.scan_base);
+        if self.scan_base == 0 {
+            self.local_labels.clear();
+            self.constant_pool.clear();
+            self.constant_pool_index.clear();
+            if self.line_numbers {
+                self.labels
+                    .retain(|name, _| !name.chars().all(|c| c.is_ascii_digit()));
+            }
+        }
+    }
+
+    fn scan_tokens(&mut self, code: String) -> Result<Vec<ASTNode>, Vec<ASMError>> {
+        self.begin_scan();
+        if code.trim().is_empty() {
+            self.program_length = 0;
+            return Ok(Vec::new());
+        }
+        let lexed = asm_lexer::lex_all(self, &code);
+        self.scan_lexed(lexed)
+    }
+
+    fn scan_lexed(
+        &mut self,
+        (tokens, errors): (Vec<Token>, Vec<ASMError>),
+    ) -> Result<Vec<ASTNode>, Vec<ASMError>> {
+        let first_new_error = self.raised_errors.len();
+        for error in errors.iter() {
+            self.raise_error(error.clone(), true);
+        }
+        if !errors.is_empty() && !self.collect_all_errors {
+            return Err(errors);
+        }
+        let mut output: Vec<ASTNode> = Vec::new();
+        let mut conditions: Vec<bool> = Vec::new();
+        for line in tokens.split(|token| token.kind == TokenKind::EndOfLine) {
+            let body = match line.first().map(|token| &token.kind) {
+                Some(TokenKind::LineNumber(_)) => &line[1..],
+                _ => line,
+            };
+            match body.first().map(|token| &token.kind) {
+                Some(TokenKind::Directive(directive)) if directive == ".if" => {
+                    let included = match &body[1..] {
+                        [Token {
+                            kind: TokenKind::Ident(name),
+                            ..
+                        }] => self.is_truthy_constant(name),
+                        _ => {
+                            self.raise_exception(".if expects a constant name", true);
+                            false
+                        }
+                    };
+                    let enclosing = conditions.last().copied().unwrap_or(true);
+                    conditions.push(enclosing && included);
+                }
+                Some(TokenKind::Directive(directive)) if directive == ".endif" => {
+                    if conditions.pop().is_none() {
+                        self.raise_exception(".endif without a matching .if", true);
+                    }
+                }
+                _ => {
+                    if conditions.last().copied().unwrap_or(true) {
+                        self.scan_line(line, &mut output);
+                        if let Some(token) = line.first() {
+                            let base = self.scan_base;
+                            self.source_lines
+                                .resize(base + output.len(), token.first_line);
+                        }
+                    }
+                }
+            }
+        }
+        if !conditions.is_empty() {
+            self.raise_exception(".if without a matching .endif", true);
+        }
+        self.resolve_local_labels(&mut output);
+        self.check_registers(&output);
+        self.check_instruction_arguments(&output);
+        self.program_length = output.len();
+        if self.raised_errors.len() > first_new_error {
+            return Err(self.raised_errors[first_new_error..].to_vec());
+        }
+        Ok(output)
+    }
+
+    fn scan_line(&mut self, mut line: &[Token], output: &mut Vec<ASTNode>) {
+        if let Some(TokenKind::LineNumber(number)) = line.first().map(|token| &token.kind) {
+            if self.labels.contains_key(number) {
+                self.raise_exception(
+                    format!("line number {} is defined twice", number).as_str(),
+                    true,
+                );
+            }
+            self.labels
+                .insert(number.clone(), self.scan_base + output.len());
+            line = &line[1..];
+        }
+        match line.first().map(|token| &token.kind) {
+            Some(TokenKind::Directive(directive)) if directive == ".align" => {
+                self.scan_align(&line[1..], output)
+            }
+            Some(TokenKind::Directive(directive)) => self.scan_directive(directive, &line[1..]),
+            Some(TokenKind::LabelDef(label)) if label.chars().all(|c| c.is_ascii_digit()) => {
+                self.local_labels
+                    .entry(label.clone())
+                    .or_default()
+                    .push(self.scan_base + output.len());
+                self.scan_line(&line[1..], output);
+            }
+            Some(TokenKind::LabelDef(label)) => {
+                self.labels
+                    .insert(label.clone(), self.scan_base + output.len());
+                self.scan_line(&line[1..], output);
+            }
+            Some(TokenKind::Ident(instruction)) => {
+                let name = instruction.clone();
+                if self.classify_instruction(&name).is_none() {
+                    if self.is_value_word(&name) {
+                        self.raise_exception(
+                            format!(
+                                "line {}: expected instruction at start of line, found {}",
+                                line[0].first_line + 1,
+                                name
+                            )
+                            .as_str(),
+                            true,
+                        );
+                    } else if self.unknown_instruction_handler.is_none() {
+                        self.raise_exception(
+                            format!(
+                                "line {}: {} is an unknown instruction",
+                                line[0].first_line + 1,
+                                name
+                            )
+                            .as_str(),
+                            true,
+                        );
+                    }
+                }
+                if let Some(max_args) = self.max_args_per_instruction {
+                    if line.len() - 1 > max_args {
+                        self.raise_exception(
+                            format!(
+                                "line {}: {} has {} arguments, at most {} are allowed",
+                                line[0].first_line + 1,
+                                name,
+                                line.len() - 1,
+                                max_args
+                            )
+                            .as_str(),
+                            true,
+                        );
+                        return;
+                    }
+                }
+                output.push(ASTNode::ASTInstruction(self.symbols.intern(&name)));
+                for token in line[1..].iter() {
+                    let argument = self.match_argument(token);
+                    output.push(self.pool_argument(argument));
+                }
+                output.push(ASTNode::ASTExprEnd);
+            }
+            _ => {}
+        }
+    }
+
+    /// Rewrites `Nb` and `Nf` references to the nearest numeric label `N:` defined at or
+    /// before, or after, the referencing instruction.
+    fn resolve_local_labels(&mut self, output: &mut [ASTNode]) {
+        let mut instruction = self.scan_base;
+        for (index, node) in output.iter_mut().enumerate() {
+            let name = match node {
+                ASTNode::ASTInstruction(_) => {
+                    instruction = self.scan_base + index;
+                    continue;
+                }
+                ASTNode::ASTValue(value) => match value.get_value_holder() {
+                    ASMValueHolder::Label(name) => name,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let (number, backward) = match (name.strip_suffix('b'), name.strip_suffix('f')) {
+                (Some(number), _) => (number, true),
+                (_, Some(number)) => (number, false),
+                _ => continue,
+            };
+            if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let occurrences = self
+                .local_labels
+                .get(number)
+                .map(Vec::as_slice)
+                .unwrap_or(&[]);
+            let destination = if backward {
+                occurrences.iter().rev().find(|line| **line <= instruction)
+            } else {
+                occurrences.iter().find(|line| **line > instruction)
+            };
+            match destination {
+                Some(destination) => {
+                    *node = ASTNode::ASTValue(ASMValue::new_from_holder(
+                        ASMValueHolder::ResolvedLabel(name, *destination),
+                        None,
+                    ))
+                }
+                None => self.raise_exception(
+                    format!(
+                        "label {} has no {} definition",
+                        name,
+                        if backward { "preceding" } else { "following" }
+                    )
+                    .as_str(),
+                    true,
+                ),
+            }
+        }
+    }
+
+    /// Reports arguments that name an instruction instead of a defined label, which
+    /// usually means two instructions ended up on one line.
+    fn check_instruction_arguments(&mut self, output: &[ASTNode]) {
+        for (index, node) in output.iter().enumerate() {
+            let name = match node {
+                ASTNode::ASTValue(value) => match value.get_value_holder() {
+                    ASMValueHolder::Label(name) => name,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if self.classify_instruction(&name).is_none() || self.labels.contains_key(&name) {
+                continue;
+            }
+            let message = match self.source_line(self.scan_base + index) {
+                Some(line) => format!(
+                    "line {}: instruction {} is used as an argument",
+                    line + 1,
+                    name
+                ),
+                None => format!("instruction {} is used as an argument", name),
+            };
+            self.raise_exception(message.as_str(), true);
+        }
+    }
+
+    /// Whether a bare word would be scanned as a value rather than a label when used
+    /// as an argument, so it cannot open an expression.
+    fn is_value_word(&self, word: &str) -> bool {
+        if let RegisterSyntax::Pattern(is_register) = self.register_syntax {
+            if is_register(word) {
+                return true;
+            }
+        }
+        match self.classify_identifier(word) {
+            ASTNode::ASTValue(value) => {
+                !matches!(value.get_value_holder(), ASMValueHolder::Label(_))
+            }
+            _ => true,
+        }
+    }
+
+    /// Reports every register reference in the scanned stream that names an undeclared
+    /// register, unless registers are created implicitly.
+    fn check_registers(&mut self, output: &[ASTNode]) {
+        if self.implicit_registers {
+            return;
+        }
+        for (index, node) in output.iter().enumerate() {
+            let mut node = node;
+            while let ASTNode::ASTIndexed(base, _) = node {
+                node = base;
+            }
+            let name = match node {
+                ASTNode::ASTRegister(name) if !self.registers.contains_key(name) => name,
+                _ => continue,
+            };
+            let message = match self.source_line(self.scan_base + index) {
+                Some(line) => format!("line {}: register {} is not defined", line + 1, name),
+                None => format!("register {} is not defined", name),
+            };
+            self.raise_exception(message.as_str(), true);
+        }
+    }
+
+    /// Pads with `nop` instructions until the number of instructions emitted so far is a
+    /// multiple of the alignment, so the next instruction's opcode index is aligned.
+    fn scan_align(&mut self, args: &[Token], output: &mut Vec<ASTNode>) {
+        let alignment = match args {
+            [Token {
+                kind: TokenKind::Number(ASMValueHolder::Int(alignment)),
+                ..
+            }] if *alignment > 0 => *alignment as usize,
+            _ => {
+                self.raise_exception(".align expects a positive integer", true);
+                return;
+            }
+        };
+        let nop = match self.classify_instruction("nop") {
+            Some(nop) => nop,
+            None => {
+                self.raise_exception(".align requires a nop instruction", true);
+                return;
+            }
+        };
+        let emitted = self.appended_program[..self.scan_base]
+            .iter()
+            .chain(output.iter())
+            .filter(|node| matches!(node, ASTNode::ASTInstruction(_)))
+            .count();
+        for _ in 0..(alignment - emitted % alignment) % alignment {
+            output.push(ASTNode::ASTInstruction(nop));
+            output.push(ASTNode::ASTExprEnd);
+        }
+    }
+
+    fn scan_directive(&mut self, directive: &str, args: &[Token]) {
+        match directive {
+            ".const" => {
+                let name = match args {
+                    [Token {
+                        kind: TokenKind::Ident(name),
+                        ..
+                    }, _] => name.clone(),
+                    _ => {
+                        self.raise_exception(".const expects a name and a value", true);
+                        return;
+                    }
+                };
+                match self.match_argument(&args[1]) {
+                    ASTNode::ASTValue(value) => {
+                        self.constants.insert(name, value);
+                    }
+                    _ => self.raise_exception(
+                        format!("{} is not a valid constant value", name).as_str(),
+                        true,
+                    ),
+                }
+            }
+            ".array" => {
+                let name = match args.first().map(|token| &token.kind) {
+                    Some(TokenKind::Ident(name)) => name.clone(),
+                    _ => {
+                        self.raise_exception(".array expects a name and its elements", true);
+                        return;
+                    }
+                };
+                let mut values: Vec<ASMValue> = Vec::new();
+                for token in args[1..].iter() {
+                    match self.match_argument(token) {
+                        ASTNode::ASTValue(value) => values.push(value),
+                        _ => {
+                            self.raise_exception(
+                                format!("{} has an invalid element", name).as_str(),
+                                true,
+                            );
+                            return;
+                        }
+                    }
+                }
+                self.constants.insert(
+                    name,
+                    ASMValue::new_from_holder(ASMValueHolder::Array(values), None),
+                );
+            }
+            _ => self.raise_exception(
+                format!("{} is an unknown directive", directive).as_str(),
+                true,
+            ),
+        }
+    }
+
+    fn is_truthy_constant(&self, name: &str) -> bool {
+        self.constants.get(name).is_some_and(ASMValue::is_truthy)
+    }
+
+    pub fn classify_instruction(&self, name: &str) -> Option<Symbol> {
+        self.symbols
+            .get(name)
+            .filter(|id| self.instructions.contains_key(id))
+    }
+
+    fn pool_argument(&mut self, argument: ASTNode) -> ASTNode {
+        let value = match &argument {
+            ASTNode::ASTValue(value) => value,
+            _ => return argument,
+        };
+        let holder = value.get_value_holder();
+        if !matches!(holder, ASMValueHolder::Str(_) | ASMValueHolder::Float(_)) {
+            return argument;
+        }
+        let key = format!("{:?}", holder);
+        if let Some(index) = self.constant_pool_index.get(&key) {
+            return ASTNode::ASTPooled(*index, self.constant_pool[*index].clone());
+        }
+        let pooled = Rc::new(value.clone());
+        self.constant_pool.push(pooled.clone());
+        self.constant_pool_index
+            .insert(key, self.constant_pool.len() - 1);
+        ASTNode::ASTPooled(self.constant_pool.len() - 1, pooled)
+    }
+
+    /// The literals pooled by the last `scan`. Streams hold their pooled values
+    /// themselves, so the next scan starting a new pool doesn't affect them. Instruction
+    /// closures take their arguments by value, so a pooled literal is still cloned each
+    /// time its instruction runs.
+    pub fn constant_pool(&self) -> &[Rc<ASMValue>] {
+        &self.constant_pool
+    }
+
+    fn match_argument(&self, token: &Token) -> ASTNode {
+        self.match_argument_kind(&token.kind)
+    }
+
+    fn match_argument_kind(&self, kind: &TokenKind) -> ASTNode {
+        match kind {
+            TokenKind::Number(value) => {
+                ASTNode::ASTValue(ASMValue::new_from_holder(value.clone(), None))
+            }
+            TokenKind::Str(value) => ASTNode::ASTValue(ASMValue::new_str(value.clone(), None)),
+            TokenKind::RegRef(register) => ASTNode::ASTRegister(register.clone()),
+            TokenKind::Ident(word) => self.classify_identifier(word),
+            TokenKind::Indexed(base, index) => {
+                ASTNode::ASTIndexed(Box::new(self.match_argument_kind(base)), *index)
+            }
+            _ => ASTNode::ASTExprEnd,
+        }
+    }
+
+    /// Classifies a bare word argument. When several categories match, registers
+    /// (with `with_bare_registers`) win over constants, and constants over labels.
+    pub fn classify_identifier(&self, word: &str) -> ASTNode {
+        if self.bare_registers && self.registers.contains_key(word) {
+            return ASTNode::ASTRegister(word.to_string());
+        }
+        match self.constants.get(word) {
+            Some(constant) => ASTNode::ASTValue(constant.clone()),
+            None => ASTNode::ASTValue(ASMValue::new_label(word.to_string(), None)),
+        }
+    }
+
+    pub fn validate(&mut self, token_stream: &[ASTNode]) -> Result<(), Vec<ASMError>> {
+        self.linting = self.collect_all_errors;
+        let result = self.validate_stream(token_stream);
+        self.linting = false;
+        result
+    }
+
+    fn validate_stream(&mut self, token_stream: &[ASTNode]) -> Result<(), Vec<ASMError>> {
+        let mark = self.error_mark();
+        let referenced_labels: HashSet<String> = token_stream
+            .iter()
+            .filter_map(|token| match token {
+                ASTNode::ASTValue(value) => match value.get_value_holder() {
+                    ASMValueHolder::Label(label) => Some(label),
+                    ASMValueHolder::ResolvedLabel(label, _) => Some(label),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        let mut unused_labels: Vec<String> = self
+            .labels
+            .keys()
+            .filter(|label| !referenced_labels.contains(*label))
+            .filter(|label| !(self.line_numbers && label.chars().all(|c| c.is_ascii_digit())))
+            .filter(|label| self.entry_point.as_deref() != Some(label.as_str()))
+            .cloned()
+            .collect();
+        unused_labels.sort();
+        for label in unused_labels {
+            self.raise_warning(format!("label {} is never referenced", label).as_str());
+        }
+        if self.raised_errors.len() > mark {
+            return Err(self.raised_errors[mark..].to_vec());
+        }
+        Ok(())
+    }
+
+    /// Rewrites arguments naming a defined label into resolved destinations, so jumps to
+    /// them need no label lookup. Other bare words, such as the comparison of `jump_if`,
+    /// are left as they are, but an undefined label in an argument that
+    /// `with_control_flow` declares as a jump target is reported.
+    pub fn resolve_labels(&self, token_stream: &mut [ASTNode]) -> Result<(), Vec<ASMError>> {
+        let mut errors: Vec<ASMError> = Vec::new();
+        let mut target: Option<usize> = None;
+        let mut argument = 0;
+        for (index, token) in token_stream.iter_mut().enumerate() {
+            match token {
+                ASTNode::ASTInstruction(instruction) => {
+                    target = match self.control_flow(*instruction) {
+                        Some(ControlFlow::Jump(position)) | Some(ControlFlow::Branch(position)) => {
+                            Some(position)
+                        }
+                        _ => None,
+                    };
+                    argument = 0;
+                    continue;
+                }
+                ASTNode::ASTExprEnd => continue,
+                _ => {}
+            }
+            let position = argument;
+            argument += 1;
+            let name = match token {
+                ASTNode::ASTValue(value) => match value.get_value_holder() {
+                    ASMValueHolder::Label(name) => name,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            match self.labels.get(&name) {
+                Some(destination) => {
+                    *token = ASTNode::ASTValue(ASMValue::new_from_holder(
+                        ASMValueHolder::ResolvedLabel(name, *destination),
+                        None,
+                    ))
+                }
+                None if target == Some(position) => {
+                    let message = match self.source_line(index) {
+                        Some(line) => format!("line {}: label {} is not defined", line + 1, name),
+                        None => format!("label {} is not defined", name),
+                    };
+                    errors.push(ASMError::Exception(message));
+                }
+                None => {}
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub(crate) fn error_mark(&self) -> usize {
+        self.raised_errors.len()
+    }
+
+    pub(crate) fn errors_since(&self, mark: usize) -> Result<(), ASMError> {
+        match self.raised_errors.get(mark) {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn interpret(&mut self, code: String) -> Result<(), ASMError> {
+        let mark = self.error_mark();
+        let ast = match self.scan(code) {
+            Ok(ast) => ast,
+            Err(errors) => return Err(errors[0].clone()),
+        };
+        if let Err(errors) = self.validate(&ast) {
+            return Err(errors[0].clone());
+        }
+        self.run(ast);
+        self.errors_since(mark)
+    }
+
+    pub fn program(&mut self, code: &str) -> Result<Program, ASMError> {
+        self.labels.clear();
+        self.constants.clear();
+        let stream = match self.scan(code.into()) {
+            Ok(stream) => stream,
+            Err(errors) => return Err(errors[0].clone()),
+        };
+        if let Err(errors) = self.validate(&stream) {
+            return Err(errors[0].clone());
+        }
+        Ok(Program::new(stream, self.labels.clone(), self))
+    }
+
+    pub fn interpret_fresh(&mut self, code: String) -> Result<(), ASMError> {
+        self.reset_run_state();
+        self.interpret(code)
+    }
+
+    /// Scans and validates a program once and keeps it on the definition for `run_loaded`.
+    pub fn load(&mut self, code: String) -> Result<(), Vec<ASMError>> {
+        let program = self.program(&code).map_err(|error| vec![error])?;
+        self.loaded_program = Some(program);
+        Ok(())
+    }
+
+    /// Runs the program stored by `load` from a fresh run state. It can be called any
+    /// number of times without scanning the source again.
+    pub fn run_loaded(&mut self) -> Result<(), ASMError> {
+        let program = match self.loaded_program.take() {
+            Some(program) => program,
+            None => return Err(ASMError::Exception("no program is loaded".to_string())),
+        };
+        self.reset_run_state();
+        let result = program.run(self);
+        self.loaded_program = Some(program);
+        result
+    }
+}
+
+pub(crate) fn instruction_starts(stream: &[ASTNode]) -> Vec<usize> {
+    stream
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| matches!(token, ASTNode::ASTInstruction(_)))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+pub fn diff_registers(
+    before: &HashMap<String, ASMValue>,
+    after: &HashMap<String, ASMValue>,
+) -> Vec<(String, ASMValue, ASMValue)> {
+    let mut changes: Vec<(String, ASMValue, ASMValue)> = after
+        .iter()
+        .filter_map(|(name, new_value)| {
+            let old_value = before
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| ASMValue::new_empty(None));
+            if old_value == *new_value {
+                None
+            } else {
+                Some((name.clone(), old_value, new_value.clone()))
+            }
+        })
+        .collect();
+    changes.sort_by(|left, right| left.0.cmp(&right.0));
+    changes
+}
+
+impl fmt::Debug for ASMDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registers: BTreeMap<&String, String> = self
+            .registers
+            .iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect();
+        let instructions: BTreeSet<&str> = self
+            .instructions
+            .keys()
+            .map(|id| self.symbol_name(*id))
+            .collect();
+        let labels: BTreeMap<&String, &usize> = self.labels.iter().collect();
+        f.debug_struct("ASMDefinition")
+            .field("registers", &registers)
+            .field("instructions", &instructions)
+            .field("labels", &labels)
+            .field("current_line", &self.current_line)
+            .field("halted", &self.halted)
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+impl Default for ASMDefinition {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,32 @@
+use core::fmt;
+
+use crate::asm_instruction::ArgKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ASMError {
+    Exception(String),
+    ArgumentMismatch {
+        instruction: String,
+        position: usize,
+        expected: ArgKind,
+        actual: String,
+    },
+}
+
+impl fmt::Display for ASMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ASMError::Exception(message) => write!(f, "{}", message),
+            ASMError::ArgumentMismatch {
+                instruction,
+                position,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} expects argument {} to be {:?}, got {}",
+                instruction, position, expected, actual
+            ),
+        }
+    }
+}
@@ -1,9 +1,81 @@
-use crate::{asm_value::ASMValue, ASMDefinition};
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    asm_value::{ASMValue, ASMValueHolder},
+    ASMDefinition,
+};
+
+pub type ASMClosure = Box<dyn FnMut(&mut ASMDefinition, Vec<ASMValue>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Reg,
+    Int,
+    Float,
+    Str,
+    Label,
+    Any,
+}
+
+impl ArgKind {
+    pub fn accepts(&self, arg: &ASMValue, resolved: &ASMValueHolder) -> bool {
+        match self {
+            ArgKind::Reg => matches!(arg.get_value_holder(), ASMValueHolder::Register(_)),
+            ArgKind::Int => matches!(
+                resolved,
+                ASMValueHolder::Int(_)
+                    | ASMValueHolder::I8(_)
+                    | ASMValueHolder::U8(_)
+                    | ASMValueHolder::I64(_)
+            ),
+            ArgKind::Float => matches!(resolved, ASMValueHolder::Float(_)),
+            ArgKind::Str => matches!(resolved, ASMValueHolder::Str(_)),
+            ArgKind::Label => matches!(
+                resolved,
+                ASMValueHolder::Label(_) | ASMValueHolder::ResolvedLabel(_, _)
+            ),
+            ArgKind::Any => true,
+        }
+    }
+}
+
+/// How an instruction moves the program counter, registered with
+/// `ASMDefinition::with_control_flow` so analyses such as `reachable_lines` don't have to
+/// know instruction names. Argument positions count from zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlFlow {
+    /// Always continues at the label in the given argument.
+    Jump(usize),
+    /// Continues at the label in the given argument or at the next instruction.
+    Branch(usize),
+    /// Moves by the instruction offset in the given argument.
+    Relative(usize),
+    /// Continues at a destination only known at run time.
+    Indirect,
+    /// Ends the program or returns to the caller.
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionDoc {
+    pub description: String,
+    pub usage: String,
+}
+
+#[derive(Clone)]
+enum ASMFunction {
+    Pointer(fn(&mut ASMDefinition, Vec<ASMValue>)),
+    Closure(Rc<RefCell<ASMClosure>>),
+}
 
 #[derive(Clone)]
 pub struct ASMInstruction {
-    function: fn(&mut ASMDefinition, Vec<ASMValue>),
+    function: ASMFunction,
     lang_definition: *mut ASMDefinition,
+    arity: Option<usize>,
+    signature: Option<Vec<ArgKind>>,
+    raw: bool,
+    doc: Option<InstructionDoc>,
 }
 
 impl ASMInstruction {
@@ -12,16 +84,82 @@ impl ASMInstruction {
         _lang_definition: *mut ASMDefinition,
     ) -> Self {
         ASMInstruction {
-            function: _function,
+            function: ASMFunction::Pointer(_function),
+            lang_definition: _lang_definition,
+            arity: None,
+            signature: None,
+            raw: false,
+            doc: None,
+        }
+    }
+
+    pub fn new_closure(_closure: ASMClosure, _lang_definition: *mut ASMDefinition) -> Self {
+        ASMInstruction {
+            function: ASMFunction::Closure(Rc::new(RefCell::new(_closure))),
             lang_definition: _lang_definition,
+            arity: None,
+            signature: None,
+            raw: false,
+            doc: None,
         }
     }
 
+    pub fn with_arity(mut self, arity: usize) -> Self {
+        self.arity = Some(arity);
+        self
+    }
+
+    pub fn with_signature(mut self, signature: &[ArgKind]) -> Self {
+        self.arity = Some(signature.len());
+        self.signature = Some(signature.to_vec());
+        self
+    }
+
+    pub fn with_raw_operands(mut self) -> Self {
+        self.arity = Some(1);
+        self.raw = true;
+        self
+    }
+
+    pub fn takes_raw_operands(&self) -> bool {
+        self.raw
+    }
+
+    pub fn with_doc(mut self, description: &str, usage: &str) -> Self {
+        self.doc = Some(InstructionDoc {
+            description: description.to_string(),
+            usage: usage.to_string(),
+        });
+        self
+    }
+
+    pub fn get_doc(&self) -> Option<&InstructionDoc> {
+        self.doc.as_ref()
+    }
+
+    pub fn get_arity(&self) -> Option<usize> {
+        self.arity
+    }
+
+    pub fn get_signature(&self) -> Option<&[ArgKind]> {
+        self.signature.as_deref()
+    }
+
+    pub fn get_lang_definition(&self) -> *mut ASMDefinition {
+        self.lang_definition
+    }
+
     pub fn call(&self, state: &mut ASMDefinition, args: Vec<ASMValue>) {
-        (self.function)(state, args);
+        match &self.function {
+            ASMFunction::Pointer(function) => function(state, args),
+            ASMFunction::Closure(closure) => match closure.try_borrow_mut() {
+                Ok(mut closure) => closure(state, args),
+                Err(_) => state.raise_exception("Instruction closure called recursively", true),
+            },
+        }
     }
 
     pub fn call_with_slice(&self, state: &mut ASMDefinition, args: &[ASMValue]) {
-        (self.function)(state, args.to_vec())
+        self.call(state, args.to_vec())
     }
 }
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+pub type Symbol = u32;
+
+/// Maps instruction names to dense ids, so the instruction table, cycle costs and
+/// instruction hooks are keyed on a `u32` and scanned streams carry ids rather than names.
+///
+/// Register names are deliberately out of scope: instruction closures reach registers by
+/// name through `ASMValue`, which has no access to the interner, so `registers`,
+/// `register_defaults` and `ASTRegister` keep string keys. `benches/interning.rs` measures
+/// the instruction lookups that are interned.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    ids: HashMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len() as Symbol;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: Symbol) -> &str {
+        &self.names[id as usize]
+    }
+}
@@ -0,0 +1,557 @@
+use std::{convert::TryFrom, io::BufRead};
+
+use crate::{
+    asm_definition::{ASMDefinition, RegisterSyntax},
+    asm_error::ASMError,
+    asm_value::ASMValueHolder,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(ASMValueHolder),
+    Str(String),
+    RegRef(String),
+    Indexed(Box<TokenKind>, usize),
+    LabelDef(String),
+    Directive(String),
+    LineNumber(String),
+    EndOfLine,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+#[derive(Default)]
+struct SourceLine {
+    first_line: usize,
+    last_line: usize,
+    words: Vec<String>,
+    raw: String,
+    word_ends: Vec<usize>,
+    length: usize,
+    unterminated_string: bool,
+}
+
+impl SourceLine {
+    fn end_word(&mut self, current: &mut String) {
+        if !current.is_empty() {
+            self.words.push(std::mem::take(current));
+            self.word_ends.push(self.raw.len());
+        }
+    }
+
+    /// Adds a character to the line, and to `word` when it is part of one. Past
+    /// `max_length` characters the line is only counted, so an overlong line is never
+    /// split into words.
+    fn push(&mut self, c: char, word: Option<&mut String>, max_length: Option<usize>) {
+        self.length += 1;
+        if max_length.is_some_and(|max_length| self.length > max_length) {
+            self.raw.clear();
+            self.words.clear();
+            self.word_ends.clear();
+            if let Some(word) = word {
+                word.clear();
+            }
+            return;
+        }
+        self.raw.push(c);
+        if let Some(word) = word {
+            word.push(c);
+        }
+    }
+
+    fn raw_after(&self, word: usize) -> &str {
+        self.raw[self.word_ends[word]..].trim()
+    }
+}
+
+pub fn lex(definition: &ASMDefinition, code: &str) -> Result<Vec<Token>, Vec<ASMError>> {
+    let (output, errors) = lex_all(definition, code);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(output)
+}
+
+pub fn lex_all(definition: &ASMDefinition, code: &str) -> (Vec<Token>, Vec<ASMError>) {
+    let mut output: Vec<Token> = Vec::new();
+    let mut errors: Vec<ASMError> = Vec::new();
+    let (lines, unterminated_comment) = split_source(
+        code,
+        definition.uses_nested_comments(),
+        definition.max_line_length(),
+        0,
+    );
+    lex_lines(
+        definition,
+        lines,
+        unterminated_comment,
+        &mut output,
+        &mut errors,
+    );
+    (output, errors)
+}
+
+/// Lexes source read line by line. Lines are buffered only while a string literal or
+/// block comment is still open, so the whole source is never held in memory at once.
+pub fn lex_reader(
+    definition: &ASMDefinition,
+    mut reader: impl BufRead,
+) -> (Vec<Token>, Vec<ASMError>) {
+    let mut output: Vec<Token> = Vec::new();
+    let mut errors: Vec<ASMError> = Vec::new();
+    let mut chunk = String::new();
+    let mut first_line = 0;
+    loop {
+        let read = match reader.read_line(&mut chunk) {
+            Ok(read) => read,
+            Err(error) => {
+                errors.push(ASMError::Exception(format!(
+                    "line {}: source could not be read: {}",
+                    first_line + 1,
+                    error
+                )));
+                break;
+            }
+        };
+        let (lines, unterminated_comment) = split_source(
+            &chunk,
+            definition.uses_nested_comments(),
+            definition.max_line_length(),
+            first_line,
+        );
+        let open = unterminated_comment.is_some()
+            || lines.last().is_some_and(|line| line.unterminated_string);
+        if open && read > 0 {
+            continue;
+        }
+        lex_lines(
+            definition,
+            lines,
+            unterminated_comment,
+            &mut output,
+            &mut errors,
+        );
+        if read == 0 {
+            break;
+        }
+        first_line += chunk.matches('\n').count();
+        chunk.clear();
+    }
+    (output, errors)
+}
+
+fn lex_lines(
+    definition: &ASMDefinition,
+    lines: Vec<SourceLine>,
+    unterminated_comment: Option<usize>,
+    output: &mut Vec<Token>,
+    errors: &mut Vec<ASMError>,
+) {
+    if let Some(line) = unterminated_comment {
+        errors.push(ASMError::Exception(format!(
+            "line {}: block comment is never terminated",
+            line + 1
+        )));
+    }
+    for line in lines {
+        let token = |kind: TokenKind| Token {
+            kind,
+            first_line: line.first_line,
+            last_line: line.last_line,
+        };
+        if line.unterminated_string {
+            errors.push(ASMError::Exception(format!(
+                "lines {}-{}: string literal is never terminated",
+                line.first_line + 1,
+                line.last_line + 1
+            )));
+            continue;
+        }
+        if let Some(max_length) = definition.max_line_length() {
+            if line.length > max_length {
+                errors.push(ASMError::Exception(format!(
+                    "line {}: line is {} characters long, at most {} are allowed",
+                    line.first_line + 1,
+                    line.length,
+                    max_length
+                )));
+                continue;
+            }
+        }
+        if line.words.is_empty() {
+            continue;
+        }
+        let mut words = line.words.iter();
+        let mut first_word = words.next();
+        if let Some(word) = first_word {
+            if definition.uses_line_numbers() && word.chars().all(|c| c.is_ascii_digit()) {
+                output.push(token(TokenKind::LineNumber(word.clone())));
+                first_word = words.next();
+            }
+        }
+        let mut raw_remainder = None;
+        let mut names_constant = false;
+        while let Some(word) = first_word {
+            match lex_line_start(definition, word) {
+                Ok(kind) => {
+                    let is_label = matches!(kind, TokenKind::LabelDef(_));
+                    names_constant = matches!(&kind, TokenKind::Directive(name) if name == ".const" || name == ".array");
+                    if let TokenKind::Ident(name) = &kind {
+                        if definition.is_raw_instruction(name) {
+                            let consumed = line.words.len() - words.len();
+                            raw_remainder = Some(line.raw_after(consumed - 1).to_string());
+                        }
+                    }
+                    output.push(token(kind));
+                    if !is_label {
+                        break;
+                    }
+                    first_word = words.next();
+                }
+                Err(message) => {
+                    errors.push(ASMError::Exception(format!(
+                        "line {}: {}",
+                        line.first_line + 1,
+                        message
+                    )));
+                    break;
+                }
+            }
+        }
+        if let Some(remainder) = raw_remainder {
+            output.push(token(TokenKind::Str(remainder)));
+            output.push(token(TokenKind::EndOfLine));
+            continue;
+        }
+        if names_constant {
+            if let Some(name) = words.as_slice().first() {
+                if reads_as_number(definition, name) {
+                    errors.push(ASMError::Exception(format!(
+                        "line {}: name {} is a valid radix-{} literal",
+                        line.first_line + 1,
+                        name,
+                        definition.default_radix()
+                    )));
+                    continue;
+                }
+            }
+        }
+        for word in words {
+            match lex_argument(definition, word) {
+                Ok(kind) => output.push(token(kind)),
+                Err(message) => errors.push(ASMError::Exception(format!(
+                    "line {}: {}",
+                    line.first_line + 1,
+                    message
+                ))),
+            }
+        }
+        output.push(token(TokenKind::EndOfLine));
+    }
+}
+
+fn lex_line_start(definition: &ASMDefinition, word: &str) -> Result<TokenKind, String> {
+    if word.starts_with('.') {
+        return Ok(TokenKind::Directive(word.to_string()));
+    }
+    if let Some(label) = word.strip_suffix(':') {
+        if !is_identifier(label) {
+            return Err(format!("{} is not a valid label", word));
+        }
+        if reads_as_number(definition, label) {
+            return Err(format!(
+                "label {} is a valid radix-{} literal",
+                label,
+                definition.default_radix()
+            ));
+        }
+        return Ok(TokenKind::LabelDef(label.to_string()));
+    }
+    let is_register = match definition.register_syntax() {
+        RegisterSyntax::Pattern(_) => false,
+        syntax => lex_register(syntax, word).is_some(),
+    };
+    if is_register || lex_literal(word, 10).is_some() {
+        return Err(format!(
+            "expected instruction at start of line, found {}",
+            word
+        ));
+    }
+    Ok(TokenKind::Ident(word.to_string()))
+}
+
+fn lex_argument(definition: &ASMDefinition, word: &str) -> Result<TokenKind, String> {
+    if let Some(value) = definition.parse_custom_literal(word) {
+        return Ok(TokenKind::Number(value));
+    }
+    if definition.uses_immediate_prefix() {
+        if let Some(literal) = word.strip_prefix('#') {
+            return lex_literal(literal, definition.default_radix())
+                .unwrap_or_else(|| Err(format!("{} is not a valid immediate value", word)));
+        }
+    }
+    if !word.starts_with('"') {
+        if let Some((base, index)) = word.rsplit_once('@') {
+            let index = index
+                .parse::<usize>()
+                .map_err(|_| format!("{} is not a valid index", word))?;
+            let base = lex_literal(base, definition.default_radix())
+                .unwrap_or_else(|| lex_reference(definition, base))?;
+            return Ok(TokenKind::Indexed(Box::new(base), index));
+        }
+    }
+    if is_local_label_reference(word) {
+        return Ok(TokenKind::Ident(word.to_string()));
+    }
+    lex_literal(word, definition.default_radix()).unwrap_or_else(|| lex_reference(definition, word))
+}
+
+/// `Nb` and `Nf` refer to the nearest numeric label `N:` before or after them. They are
+/// checked ahead of numbers so `0b` is not a binary prefix and `1b` is not hexadecimal.
+fn is_local_label_reference(word: &str) -> bool {
+    match word.strip_suffix('b').or_else(|| word.strip_suffix('f')) {
+        Some(number) => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn lex_literal(word: &str, default_radix: u32) -> Option<Result<TokenKind, String>> {
+    if word.starts_with('"') {
+        return Some(lex_string(word).map(TokenKind::Str));
+    }
+    lex_number(word, default_radix).map(|number| number.map(TokenKind::Number))
+}
+
+fn lex_reference(definition: &ASMDefinition, word: &str) -> Result<TokenKind, String> {
+    if let Some(register) = lex_register(definition.register_syntax(), word) {
+        return Ok(TokenKind::RegRef(register));
+    }
+    if is_identifier(word) {
+        return Ok(TokenKind::Ident(word.to_string()));
+    }
+    Err(format!("{} is not a valid argument", word))
+}
+
+/// Whether a name would be lexed as a number where it is referenced, so a label or
+/// constant defined with it could never be used. Numeric local labels are exempt.
+fn reads_as_number(definition: &ASMDefinition, name: &str) -> bool {
+    !name.chars().all(|c| c.is_ascii_digit())
+        && matches!(
+            lex_literal(name, definition.default_radix()),
+            Some(Ok(TokenKind::Number(_)))
+        )
+}
+
+/// Labels, bare words and register names are made of Unicode letters and digits.
+/// Anything else, including combining marks, makes the word invalid rather than
+/// being dropped, so decomposed text has to be normalized to composed form first.
+fn is_identifier(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(char::is_alphanumeric)
+}
+
+fn lex_string(word: &str) -> Result<String, String> {
+    if word.len() < 2 || !word.ends_with('"') {
+        return Err(format!("{} is an unterminated string", word));
+    }
+    let mut value = String::new();
+    let mut chars = word[1..word.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some(escaped) => value.push(escaped),
+            None => value.push(c),
+        }
+    }
+    Ok(value)
+}
+
+fn lex_number(word: &str, default_radix: u32) -> Option<Result<ASMValueHolder, String>> {
+    let invalid = || format!("{} is not a valid numeric literal", word);
+    let unsigned = word.strip_prefix('-').unwrap_or(word);
+    let sign = &word[..word.len() - unsigned.len()];
+    if unsigned.starts_with('_') && unsigned[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        return Some(Err(invalid()));
+    }
+    // Words that open with a letter are only numbers in radixes that have letter digits,
+    // and fall back to being identifiers when they do not parse.
+    let letter_led = !unsigned.starts_with(|c: char| c.is_ascii_digit());
+    if letter_led && !unsigned.starts_with(|c: char| c.is_digit(default_radix)) {
+        return None;
+    }
+    let (radix, body) = match unsigned.get(..2) {
+        Some("0x") | Some("0X") if !letter_led => (16, &unsigned[2..]),
+        Some("0b") | Some("0B") if !letter_led => (2, &unsigned[2..]),
+        _ => (default_radix, unsigned),
+    };
+    let decimal = radix == 10 && body.len() == unsigned.len();
+    let digits_end = body
+        .find(|c: char| !c.is_digit(radix) && c != '_' && !(decimal && c == '.'))
+        .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(digits_end);
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+        || digits.contains("_.")
+        || digits.contains("._")
+    {
+        return if letter_led {
+            None
+        } else {
+            Some(Err(invalid()))
+        };
+    }
+    let digits = digits.replace('_', "");
+    if !decimal {
+        return match lex_radix_number(sign, &digits, radix, suffix) {
+            Some(holder) => Some(Ok(holder)),
+            None if letter_led => None,
+            None => Some(Err(invalid())),
+        };
+    }
+    let number = format!("{}{}", sign, digits);
+    let holder = match suffix {
+        "" if number.contains('.') => number.parse::<f32>().ok().map(ASMValueHolder::Float),
+        "" | "i32" => number.parse::<i32>().ok().map(ASMValueHolder::Int),
+        "i8" => number.parse::<i8>().ok().map(ASMValueHolder::I8),
+        "u8" => number.parse::<u8>().ok().map(ASMValueHolder::U8),
+        "i64" => number.parse::<i64>().ok().map(ASMValueHolder::I64),
+        "f32" => number.parse::<f32>().ok().map(ASMValueHolder::Float),
+        _ => return None,
+    };
+    Some(holder.ok_or_else(invalid))
+}
+
+fn lex_radix_number(sign: &str, digits: &str, radix: u32, suffix: &str) -> Option<ASMValueHolder> {
+    let magnitude = i128::from_str_radix(digits, radix).ok()?;
+    let value = if sign.is_empty() {
+        magnitude
+    } else {
+        -magnitude
+    };
+    match suffix {
+        "" | "i32" => i32::try_from(value).ok().map(ASMValueHolder::Int),
+        "i8" => i8::try_from(value).ok().map(ASMValueHolder::I8),
+        "u8" => u8::try_from(value).ok().map(ASMValueHolder::U8),
+        "i64" => i64::try_from(value).ok().map(ASMValueHolder::I64),
+        _ => None,
+    }
+}
+
+fn lex_register(syntax: RegisterSyntax, word: &str) -> Option<String> {
+    match syntax {
+        RegisterSyntax::Brackets => word
+            .strip_prefix('[')
+            .and_then(|word| word.strip_suffix(']'))
+            .filter(|name| is_identifier(name))
+            .map(|name| name.to_string()),
+        RegisterSyntax::Prefix(sigil) => word
+            .strip_prefix(sigil)
+            .filter(|name| is_identifier(name))
+            .map(|name| name.to_string()),
+        RegisterSyntax::Pattern(is_register) => {
+            if is_register(word) {
+                return Some(word.to_string());
+            }
+            None
+        }
+    }
+}
+
+fn split_source(
+    code: &str,
+    nested_comments: bool,
+    max_length: Option<usize>,
+    first_line: usize,
+) -> (Vec<SourceLine>, Option<usize>) {
+    let mut output: Vec<SourceLine> = Vec::new();
+    let mut pending = SourceLine {
+        first_line,
+        ..SourceLine::default()
+    };
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut block_depth = 0;
+    let mut block_start = 0;
+    let mut line = first_line;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' && !in_string {
+            pending.end_word(&mut current);
+            pending.last_line = line;
+            output.push(std::mem::take(&mut pending));
+            in_comment = false;
+            line += 1;
+            pending.first_line = line;
+            continue;
+        }
+        if c == '\n' {
+            line += 1;
+        }
+        if in_comment {
+            continue;
+        }
+        if block_depth > 0 {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                block_depth -= 1;
+            } else if nested_comments && c == '/' && chars.peek() == Some(&'*') {
+                chars.next();
+                block_depth += 1;
+            }
+            continue;
+        }
+        if in_string {
+            pending.push(c, Some(&mut current), max_length);
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    pending.push(escaped, Some(&mut current), max_length);
+                }
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                pending.push(c, Some(&mut current), max_length);
+            }
+            ';' if chars.peek() == Some(&';') => in_comment = true,
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                block_depth = 1;
+                block_start = line;
+                pending.end_word(&mut current);
+                pending.push(' ', None, max_length);
+            }
+            c if c.is_whitespace() => {
+                pending.end_word(&mut current);
+                pending.push(c, None, max_length);
+            }
+            c => pending.push(c, Some(&mut current), max_length),
+        }
+    }
+    pending.end_word(&mut current);
+    pending.last_line = line;
+    pending.unterminated_string = in_string;
+    output.push(pending);
+    let unterminated_comment = if block_depth > 0 {
+        Some(block_start)
+    } else {
+        None
+    };
+    (output, unterminated_comment)
+}
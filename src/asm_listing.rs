@@ -0,0 +1,65 @@
+use crate::asm_definition::{ASMDefinition, ASTNode, RegisterSyntax};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    /// Position of the instruction among the instructions of the stream.
+    pub index: usize,
+    /// Zero-based source line the instruction was scanned from, as `source_line` reports it.
+    pub source_line: Option<usize>,
+    pub address: usize,
+    pub label: Option<String>,
+    pub mnemonic: String,
+    pub args: Vec<String>,
+}
+
+pub fn listing(definition: &ASMDefinition, stream: &[ASTNode]) -> Vec<ListingLine> {
+    let mut output: Vec<ListingLine> = Vec::new();
+    for (address, token) in stream.iter().enumerate() {
+        match token {
+            ASTNode::ASTInstruction(mnemonic) => output.push(ListingLine {
+                index: output.len(),
+                source_line: definition.source_line(address),
+                address,
+                label: label_at(definition, address),
+                mnemonic: definition.symbol_name(*mnemonic).to_string(),
+                args: Vec::new(),
+            }),
+            ASTNode::ASTExprEnd => {}
+            argument => {
+                if let Some(line) = output.last_mut() {
+                    line.args.push(render_argument(definition, argument));
+                }
+            }
+        }
+    }
+    output
+}
+
+fn label_at(definition: &ASMDefinition, address: usize) -> Option<String> {
+    definition
+        .labels
+        .iter()
+        .filter(|(_, line)| **line == address)
+        .map(|(label, _)| label.clone())
+        .min()
+}
+
+fn render_argument(definition: &ASMDefinition, argument: &ASTNode) -> String {
+    match argument {
+        ASTNode::ASTRegister(register) => render_register(definition.register_syntax(), register),
+        ASTNode::ASTIndexed(base, index) => {
+            format!("{}@{}", render_argument(definition, base), index)
+        }
+        ASTNode::ASTValue(value) => value.to_string(),
+        ASTNode::ASTPooled(_, value) => value.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn render_register(syntax: RegisterSyntax, register: &str) -> String {
+    match syntax {
+        RegisterSyntax::Brackets => format!("[{}]", register),
+        RegisterSyntax::Prefix(sigil) => format!("{}{}", sigil, register),
+        RegisterSyntax::Pattern(_) => register.to_string(),
+    }
+}
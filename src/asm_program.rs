@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::{
+    asm_definition::{ASMDefinition, ASTNode},
+    asm_error::ASMError,
+};
+
+/// A scanned and validated program that can be run any number of times.
+///
+/// Instruction nodes carry ids from the interner of the definition that scanned them,
+/// so the program also keeps each instruction's mnemonic and re-binds the ids against
+/// whichever definition it is run on.
+#[derive(Clone)]
+pub struct Program {
+    pub stream: Vec<ASTNode>,
+    pub labels: HashMap<String, usize>,
+    mnemonics: Vec<String>,
+}
+
+impl Program {
+    pub fn new(
+        stream: Vec<ASTNode>,
+        labels: HashMap<String, usize>,
+        definition: &ASMDefinition,
+    ) -> Self {
+        let mnemonics = stream
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::ASTInstruction(symbol) => {
+                    Some(definition.symbol_name(*symbol).to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        Program {
+            stream,
+            labels,
+            mnemonics,
+        }
+    }
+
+    pub fn run(&self, definition: &mut ASMDefinition) -> Result<(), ASMError> {
+        let mark = definition.error_mark();
+        let stream = self.bind(definition);
+        definition.labels = self.labels.clone();
+        definition.run(stream);
+        definition.errors_since(mark)
+    }
+
+    fn bind(&self, definition: &mut ASMDefinition) -> Vec<ASTNode> {
+        let mut mnemonics = self.mnemonics.iter();
+        self.stream
+            .iter()
+            .map(|node| match node {
+                ASTNode::ASTInstruction(_) => match mnemonics.next() {
+                    Some(mnemonic) => ASTNode::ASTInstruction(definition.intern(mnemonic)),
+                    None => node.clone(),
+                },
+                _ => node.clone(),
+            })
+            .collect()
+    }
+}
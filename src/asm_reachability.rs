@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::{
+    asm_definition::ASMDefinition, asm_definition::ASTNode, asm_instruction::ControlFlow,
+    asm_value::ASMValueHolder,
+};
+
+struct Instruction<'a> {
+    start: usize,
+    flow: Option<ControlFlow>,
+    args: &'a [ASTNode],
+}
+
+pub fn reachable_lines(definition: &ASMDefinition, stream: &[ASTNode]) -> HashSet<usize> {
+    let instructions = collect_instructions(definition, stream);
+    let mut reachable: HashSet<usize> = HashSet::new();
+    let entry = match definition.entry_point() {
+        Some(label) => match definition.labels.get(label) {
+            Some(line) => *line,
+            None => return reachable,
+        },
+        None => 0,
+    };
+    let mut pending: Vec<usize> = instruction_at(&instructions, entry).into_iter().collect();
+    while let Some(index) = pending.pop() {
+        if !reachable.insert(instructions[index].start) {
+            continue;
+        }
+        let instruction = &instructions[index];
+        let successors: Vec<usize> = match instruction.flow {
+            Some(ControlFlow::Stop) => Vec::new(),
+            Some(ControlFlow::Jump(argument)) => {
+                label_target(definition, instruction.args.get(argument))
+                    .into_iter()
+                    .collect()
+            }
+            Some(ControlFlow::Branch(argument)) => {
+                label_target(definition, instruction.args.get(argument))
+                    .into_iter()
+                    .chain(Some(instruction.start + 1))
+                    .collect()
+            }
+            Some(ControlFlow::Relative(argument)) => {
+                match relative_target(&instructions, index, instruction.args.get(argument)) {
+                    Some(target) => vec![instructions[target].start],
+                    None => {
+                        return instructions
+                            .iter()
+                            .map(|instruction| instruction.start)
+                            .collect()
+                    }
+                }
+            }
+            Some(ControlFlow::Indirect) => {
+                return instructions
+                    .iter()
+                    .map(|instruction| instruction.start)
+                    .collect()
+            }
+            None => vec![instruction.start + 1],
+        };
+        for line in successors {
+            pending.extend(instruction_at(&instructions, line));
+        }
+    }
+    reachable
+}
+
+fn collect_instructions<'a>(
+    definition: &'a ASMDefinition,
+    stream: &'a [ASTNode],
+) -> Vec<Instruction<'a>> {
+    let mut output: Vec<Instruction<'a>> = Vec::new();
+    for (start, token) in stream.iter().enumerate() {
+        if let ASTNode::ASTInstruction(mnemonic) = token {
+            let end = stream[start..]
+                .iter()
+                .position(|token| matches!(token, ASTNode::ASTExprEnd))
+                .map_or(stream.len(), |offset| start + offset);
+            output.push(Instruction {
+                start,
+                flow: definition.control_flow(*mnemonic),
+                args: &stream[start + 1..end],
+            });
+        }
+    }
+    output
+}
+
+fn instruction_at(instructions: &[Instruction], line: usize) -> Option<usize> {
+    instructions
+        .iter()
+        .position(|instruction| instruction.start >= line)
+}
+
+fn label_target(definition: &ASMDefinition, argument: Option<&ASTNode>) -> Option<usize> {
+    let value = match argument {
+        Some(ASTNode::ASTValue(value)) => value,
+        _ => return None,
+    };
+    match value.get_value_holder() {
+        ASMValueHolder::Label(label) => definition.labels.get(&label).copied(),
+        ASMValueHolder::ResolvedLabel(_, destination) => Some(destination),
+        ASMValueHolder::Int(line_number) => {
+            definition.labels.get(&line_number.to_string()).copied()
+        }
+        _ => None,
+    }
+}
+
+fn relative_target(
+    instructions: &[Instruction],
+    index: usize,
+    argument: Option<&ASTNode>,
+) -> Option<usize> {
+    let offset = match argument {
+        Some(ASTNode::ASTValue(value)) => value.try_into_i32().ok()?,
+        _ => return None,
+    };
+    let target = (index as i64).checked_add(offset as i64)?;
+    if target < 0 || target as usize >= instructions.len() {
+        return None;
+    }
+    Some(target as usize)
+}
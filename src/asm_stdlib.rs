@@ -0,0 +1,636 @@
+//! Standard instructions that can be added to a definition with `insert_stdlib`.
+//!
+//! Instructions that write results take their destination registers as the
+//! leading arguments, followed by their source operands, e.g. `divmod [q] [r] a b`.
+
+use std::{cmp::Ordering, convert::TryFrom};
+
+use crate::{
+    asm_definition::ASMDefinition,
+    asm_instruction::ControlFlow,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+pub fn insert_stdlib(definition: ASMDefinition) -> ASMDefinition {
+    definition
+        .insert_instruction_with_arity("jr", 1, jr)
+        .insert_instruction_with_arity("divmod", 4, divmod)
+        .insert_instruction_with_arity("concat", 3, concat)
+        .insert_instruction_with_arity("itof", 1, itof)
+        .insert_instruction_with_arity("ftoi", 1, ftoi)
+        .insert_instruction_with_arity("tostr", 2, tostr)
+        .insert_instruction_with_arity("halt", 0, halt)
+        .insert_instruction_with_arity("exit", 1, exit)
+        .insert_instruction_with_arity("nop", 0, nop)
+        .insert_instruction_with_arity("rand", 2, rand)
+        .insert_instruction_with_arity("goto", 1, goto)
+        .insert_instruction_with_arity("jmpr", 1, jmpr)
+        .insert_instruction_with_arity("jump_if", 4, jump_if)
+        .insert_instruction_with_arity("len", 2, len)
+        .insert_instruction_with_arity("substr", 4, substr)
+        .insert_instruction("printf", printf)
+        .insert_instruction_with_arity("load", 2, load)
+        .insert_instruction_with_arity("store", 2, store)
+        .insert_instruction_with_arity("memcpy", 3, memcpy)
+        .insert_instruction_with_arity("fill", 3, fill)
+        .insert_instruction_with_arity("call", 1, call)
+        .insert_instruction_with_arity("ret", 0, ret)
+        .insert_instruction_with_arity("swap", 2, swap)
+        .insert_instruction_with_arity("cmov", 3, cmov)
+        .insert_instruction_with_arity("clear", 1, clear)
+        .insert_instruction_with_arity("clear_all", 0, clear_all)
+        .insert_instruction_with_arity("inc", 1, inc)
+        .insert_instruction_with_arity("dec", 1, dec)
+        .insert_instruction_with_arity("dumpmem", 2, dumpmem)
+        .insert_instruction_with_arity("cmp", 2, cmp)
+        .insert_instruction_with_arity("getpc", 1, getpc)
+        .insert_raw_instruction("help", help)
+        .insert_instruction_with_arity("jeq", 1, jeq)
+        .insert_instruction_with_arity("jne", 1, jne)
+        .insert_instruction_with_arity("jlt", 1, jlt)
+        .insert_instruction_with_arity("jle", 1, jle)
+        .insert_instruction_with_arity("jgt", 1, jgt)
+        .insert_instruction_with_arity("jge", 1, jge)
+        .with_control_flow("halt", ControlFlow::Stop)
+        .with_control_flow("exit", ControlFlow::Stop)
+        .with_control_flow("ret", ControlFlow::Stop)
+        .with_control_flow("goto", ControlFlow::Jump(0))
+        .with_control_flow("call", ControlFlow::Branch(0))
+        .with_control_flow("jump_if", ControlFlow::Branch(3))
+        .with_control_flow("jeq", ControlFlow::Branch(0))
+        .with_control_flow("jne", ControlFlow::Branch(0))
+        .with_control_flow("jlt", ControlFlow::Branch(0))
+        .with_control_flow("jle", ControlFlow::Branch(0))
+        .with_control_flow("jgt", ControlFlow::Branch(0))
+        .with_control_flow("jge", ControlFlow::Branch(0))
+        .with_control_flow("jr", ControlFlow::Relative(0))
+        .with_control_flow("jmpr", ControlFlow::Indirect)
+}
+
+fn expect_int(state: &mut ASMDefinition, name: &str, value: &ASMValue) -> Option<i32> {
+    match state.resolve_arg(value) {
+        Ok(ASMValueHolder::Int(value)) => Some(value),
+        Err(error) => {
+            state.raise_error(error, true);
+            None
+        }
+        Ok(_) => {
+            state.raise_exception(
+                format!("{} expects an integer argument", name).as_str(),
+                true,
+            );
+            None
+        }
+    }
+}
+
+fn expect_address(state: &mut ASMDefinition, name: &str, value: &ASMValue) -> Option<usize> {
+    match expect_int(state, name, value) {
+        Some(address) if address >= 0 => Some(address as usize),
+        Some(_) => {
+            state.raise_exception(
+                format!("{} expects a non-negative address", name).as_str(),
+                true,
+            );
+            None
+        }
+        None => None,
+    }
+}
+
+fn expect_destinations(
+    state: &mut ASMDefinition,
+    name: &str,
+    args: &[ASMValue],
+    count: usize,
+) -> bool {
+    for (position, arg) in args.iter().take(count).enumerate() {
+        if let ASMValueHolder::Register(_) = arg.get_value_holder() {
+            continue;
+        }
+        state.raise_exception(
+            format!("{} expects argument {} to be a register", name, position).as_str(),
+            true,
+        );
+        return false;
+    }
+    true
+}
+
+pub fn jr(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if let Some(offset) = expect_int(state, "jr", &args[0]) {
+        state.jump_relative(offset);
+    }
+}
+
+pub fn divmod(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "divmod", &args, 2) {
+        return;
+    }
+    let dividend = match expect_int(state, "divmod", &args[2]) {
+        Some(value) => value,
+        None => return,
+    };
+    let divisor = match expect_int(state, "divmod", &args[3]) {
+        Some(value) => value,
+        None => return,
+    };
+    match (dividend.checked_div(divisor), dividend.checked_rem(divisor)) {
+        (Some(quotient), Some(remainder)) => {
+            args[0].try_modify_register(ASMValue::new_int(quotient, args[0].get_lang_definition()));
+            args[1]
+                .try_modify_register(ASMValue::new_int(remainder, args[1].get_lang_definition()));
+        }
+        _ => state.raise_exception("divmod by zero or overflow", true),
+    }
+}
+
+pub fn concat(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "concat", &args, 1) {
+        return;
+    }
+    match args[1].resolve().concat(&args[2].resolve()) {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(error) => state.raise_exception(error, true),
+    }
+}
+
+fn convert_register(
+    state: &mut ASMDefinition,
+    name: &str,
+    args: &[ASMValue],
+    convert: fn(&ASMValue) -> Result<ASMValue, &str>,
+) {
+    if !expect_destinations(state, name, args, 1) {
+        return;
+    }
+    match convert(&args[0].resolve()) {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(message) => state.raise_exception(format!("{}: {}", name, message).as_str(), true),
+    }
+}
+
+pub fn itof(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    convert_register(state, "itof", &args, ASMValue::to_float);
+}
+
+pub fn ftoi(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    convert_register(state, "ftoi", &args, ASMValue::to_int);
+}
+
+pub fn tostr(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "tostr", &args, 1) {
+        return;
+    }
+    match args[1].resolve().to_str() {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(message) => state.raise_exception(format!("tostr: {}", message).as_str(), true),
+    }
+}
+
+pub fn halt(state: &mut ASMDefinition, _args: Vec<ASMValue>) {
+    state.halt();
+}
+
+pub fn exit(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if let Some(code) = expect_int(state, "exit", &args[0]) {
+        state.exit(code);
+    }
+}
+
+pub fn nop(_state: &mut ASMDefinition, _args: Vec<ASMValue>) {}
+
+pub fn rand(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "rand", &args, 1) {
+        return;
+    }
+    let max = match expect_int(state, "rand", &args[1]) {
+        Some(max) if max > 0 => max,
+        Some(_) => {
+            state.raise_exception("rand expects a positive maximum", true);
+            return;
+        }
+        None => return,
+    };
+    let value = (state.next_random() % max as u64) as i32;
+    args[0].try_modify_register(ASMValue::new_int(value, args[0].get_lang_definition()));
+}
+
+pub fn goto(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let label = match args[0].resolve().get_value_holder() {
+        ASMValueHolder::Label(label) => label,
+        ASMValueHolder::ResolvedLabel(_, destination) => {
+            state.jump(destination);
+            return;
+        }
+        ASMValueHolder::Int(line_number) => {
+            if let Err(error) = state.jump_to_line(line_number) {
+                state.raise_exception(error.to_string().as_str(), true);
+            }
+            return;
+        }
+        _ => {
+            state.raise_exception("goto expects a label or a line number", true);
+            return;
+        }
+    };
+    if let Err(error) = state.jump_to_label_name(&label) {
+        state.raise_exception(error.to_string().as_str(), true);
+    }
+}
+
+pub fn jmpr(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "jmpr", &args, 1) {
+        return;
+    }
+    state.jump_to_value(args[0].clone());
+}
+
+pub fn jump_if(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let left = args[0].resolve();
+    let right = args[2].resolve();
+    let taken = match args[1].get_value_holder() {
+        ASMValueHolder::Label(comparison) => match comparison.as_str() {
+            "eq" => left.equals(&right),
+            "lt" => left.less_than(&right),
+            "le" => left.less_equal(&right),
+            "gt" => left.greater_than(&right),
+            "ge" => left.greater_equal(&right),
+            _ => {
+                state.raise_exception(
+                    format!("{} is not a valid comparison", comparison).as_str(),
+                    true,
+                );
+                return;
+            }
+        },
+        _ => {
+            state.raise_exception("jump_if expects a comparison of eq, lt, le, gt or ge", true);
+            return;
+        }
+    };
+    if taken {
+        state.jump_to_label(args[3].clone());
+    }
+}
+
+pub fn len(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "len", &args, 1) {
+        return;
+    }
+    match args[1].resolve().char_len() {
+        Ok(length) => args[0].try_modify_register(ASMValue::new_int(
+            length as i32,
+            args[0].get_lang_definition(),
+        )),
+        Err(error) => state.raise_exception(error, true),
+    }
+}
+
+pub fn substr(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "substr", &args, 1) {
+        return;
+    }
+    let (start, length) = match (
+        expect_int(state, "substr", &args[2]),
+        expect_int(state, "substr", &args[3]),
+    ) {
+        (Some(start), Some(length)) if start >= 0 && length >= 0 => (start, length),
+        (Some(_), Some(_)) => {
+            state.raise_exception("substr expects a non-negative start and length", true);
+            return;
+        }
+        _ => return,
+    };
+    match args[1].resolve().substring(start as usize, length as usize) {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(error) => state.raise_exception(error, true),
+    }
+}
+
+pub fn printf(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let template = match args.first().map(|arg| arg.resolve().get_value_holder()) {
+        Some(ASMValueHolder::Str(template)) => template,
+        _ => {
+            state.raise_exception("printf expects a format string", true);
+            return;
+        }
+    };
+    let mut values = args[1..].iter().map(|arg| arg.resolve());
+    let mut output = String::new();
+    let mut used = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let specifier = match chars.next() {
+            Some('%') => {
+                output.push('%');
+                continue;
+            }
+            Some(specifier) => specifier,
+            None => {
+                state.raise_exception("printf format string ends with a lone %", true);
+                return;
+            }
+        };
+        let value = match values.next() {
+            Some(value) => value,
+            None => {
+                used += 1;
+                continue;
+            }
+        };
+        used += 1;
+        let matches = match (specifier, value.get_value_holder()) {
+            (
+                'd',
+                ASMValueHolder::Int(_)
+                | ASMValueHolder::I8(_)
+                | ASMValueHolder::U8(_)
+                | ASMValueHolder::I64(_),
+            ) => true,
+            ('f', ASMValueHolder::Float(_)) => true,
+            ('s', ASMValueHolder::Str(_)) => true,
+            ('d' | 'f' | 's', _) => false,
+            _ => {
+                state.raise_exception(
+                    format!("%{} is not a valid printf specifier", specifier).as_str(),
+                    true,
+                );
+                return;
+            }
+        };
+        if !matches {
+            state.raise_exception(
+                format!("printf %{} does not accept {}", specifier, value).as_str(),
+                true,
+            );
+            return;
+        }
+        output.push_str(&value.to_string());
+    }
+    if used != args.len() - 1 {
+        state.raise_exception(
+            format!(
+                "printf format expects {} arguments, got {}",
+                used,
+                args.len() - 1
+            )
+            .as_str(),
+            true,
+        );
+        return;
+    }
+    state.write_output(&output);
+}
+
+pub fn load(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "load", &args, 1) {
+        return;
+    }
+    let address = match expect_address(state, "load", &args[1]) {
+        Some(address) => address,
+        None => return,
+    };
+    match state.read_memory(address) {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(error) => state.raise_error(error, true),
+    }
+}
+
+pub fn store(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let address = match expect_address(state, "store", &args[0]) {
+        Some(address) => address,
+        None => return,
+    };
+    let value = args[1].resolve();
+    if let Err(error) = state.write_memory(address, value) {
+        state.raise_error(error, true);
+    }
+}
+
+pub fn memcpy(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let destination = match expect_address(state, "memcpy", &args[0]) {
+        Some(address) => address,
+        None => return,
+    };
+    let source = match expect_address(state, "memcpy", &args[1]) {
+        Some(address) => address,
+        None => return,
+    };
+    let length = match expect_address(state, "memcpy", &args[2]) {
+        Some(length) => length,
+        None => return,
+    };
+    if let Err(error) = state.copy_memory(destination, source, length) {
+        state.raise_error(error, true);
+    }
+}
+
+pub fn fill(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let start = match expect_address(state, "fill", &args[0]) {
+        Some(address) => address,
+        None => return,
+    };
+    let length = match expect_address(state, "fill", &args[1]) {
+        Some(length) => length,
+        None => return,
+    };
+    if let Err(error) = state.fill_memory(start, length, args[2].resolve()) {
+        state.raise_error(error, true);
+    }
+}
+
+pub fn dumpmem(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let start = match expect_address(state, "dumpmem", &args[0]) {
+        Some(address) => address,
+        None => return,
+    };
+    let length = match expect_address(state, "dumpmem", &args[1]) {
+        Some(length) => length,
+        None => return,
+    };
+    let mut dump = String::new();
+    for (offset, value) in state.dump_memory(start, length).iter().enumerate() {
+        dump.push_str(format!("0x{:04x}: {}\n", start + offset, value).as_str());
+    }
+    state.write_output(&dump);
+}
+
+pub fn call(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    state.call_label(args[0].clone());
+}
+
+pub fn ret(state: &mut ASMDefinition, _args: Vec<ASMValue>) {
+    state.return_from_call();
+}
+
+pub fn swap(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "swap", &args, 2) {
+        return;
+    }
+    let (first, second) = match (args[0].get_value_holder(), args[1].get_value_holder()) {
+        (ASMValueHolder::Register(first), ASMValueHolder::Register(second)) => (first, second),
+        _ => return,
+    };
+    if first == second {
+        return;
+    }
+    let (first_value, second_value) =
+        match (state.get_register(&first), state.get_register(&second)) {
+            (Some(first_value), Some(second_value)) => (first_value, second_value),
+            _ => {
+                state.raise_exception("swap expects both registers to be defined", true);
+                return;
+            }
+        };
+    let swapped = state
+        .set_register(&first, second_value)
+        .and_then(|_| state.set_register(&second, first_value));
+    if let Err(error) = swapped {
+        state.raise_error(error, true);
+    }
+}
+
+pub fn cmov(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let condition = match state.resolve_arg(&args[0]) {
+        Ok(holder) => ASMValue::new_from_holder(holder, None).is_truthy(),
+        Err(error) => {
+            state.raise_error(error, true);
+            return;
+        }
+    };
+    let destination = match args[1].get_value_holder() {
+        ASMValueHolder::Register(name) => name,
+        _ => {
+            state.raise_exception("cmov expects argument 1 to be a register", true);
+            return;
+        }
+    };
+    if !condition {
+        return;
+    }
+    if let Err(error) = state.set_register(&destination, args[2].resolve()) {
+        state.raise_error(error, true);
+    }
+}
+
+pub fn clear(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "clear", &args, 1) {
+        return;
+    }
+    if let ASMValueHolder::Register(name) = args[0].get_value_holder() {
+        if let Err(error) = state.reset_register(&name) {
+            state.raise_error(error, true);
+        }
+    }
+}
+
+pub fn clear_all(state: &mut ASMDefinition, _args: Vec<ASMValue>) {
+    if let Err(error) = state.reset_registers() {
+        state.raise_error(error, true);
+    }
+}
+
+fn offset_register(state: &mut ASMDefinition, name: &str, args: &[ASMValue], delta: i64) {
+    if !expect_destinations(state, name, args, 1) {
+        return;
+    }
+    let current = match state.resolve_arg(&args[0]) {
+        Ok(holder) => ASMValue::new_from_holder(holder, args[0].get_lang_definition()),
+        Err(error) => {
+            state.raise_error(error, true);
+            return;
+        }
+    };
+    match current.offset(delta, state.arithmetic_mode()) {
+        Ok(value) => args[0].try_modify_register(value),
+        Err(message) => state.raise_exception(format!("{}: {}", name, message).as_str(), true),
+    }
+}
+
+pub fn inc(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    offset_register(state, "inc", &args, 1);
+}
+
+pub fn dec(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    offset_register(state, "dec", &args, -1);
+}
+
+pub fn cmp(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    match args[0].resolve().compare(&args[1].resolve()) {
+        Some(comparison) => state.set_comparison(comparison),
+        None => state.raise_exception("cmp cannot order the given values", true),
+    }
+}
+
+fn jump_on_comparison(
+    state: &mut ASMDefinition,
+    name: &str,
+    args: &[ASMValue],
+    taken: fn(Ordering) -> bool,
+) {
+    match state.comparison() {
+        Some(comparison) if taken(comparison) => state.jump_to_label(args[0].clone()),
+        Some(_) => {}
+        None => state.raise_exception(
+            format!("{} has no comparison result to branch on", name).as_str(),
+            true,
+        ),
+    }
+}
+
+pub fn jeq(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jeq", &args, Ordering::is_eq);
+}
+
+pub fn jne(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jne", &args, Ordering::is_ne);
+}
+
+pub fn jlt(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jlt", &args, Ordering::is_lt);
+}
+
+pub fn jle(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jle", &args, Ordering::is_le);
+}
+
+pub fn jgt(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jgt", &args, Ordering::is_gt);
+}
+
+pub fn jge(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    jump_on_comparison(state, "jge", &args, Ordering::is_ge);
+}
+
+pub fn getpc(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    if !expect_destinations(state, "getpc", &args, 1) {
+        return;
+    }
+    let address = state.instruction_address();
+    match i32::try_from(address) {
+        Ok(address) => {
+            args[0].try_modify_register(ASMValue::new_int(address, args[0].get_lang_definition()))
+        }
+        Err(_) => state.raise_exception("getpc address does not fit in an integer", true),
+    }
+}
+
+pub fn help(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    let name = match args.first().map(|arg| arg.get_value_holder()) {
+        Some(ASMValueHolder::Str(text)) if !text.trim().is_empty() => text.trim().to_string(),
+        _ => {
+            state.raise_exception("help expects an instruction name", true);
+            return;
+        }
+    };
+    let text = match state.instruction_doc(&name) {
+        Some(doc) => format!("{}: {}\n", doc.usage, doc.description),
+        None => format!("{}: no documentation\n", name),
+    };
+    state.write_output(&text);
+}
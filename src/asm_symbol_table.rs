@@ -0,0 +1,37 @@
+use crate::asm_definition::ASMDefinition;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolTable {
+    pub labels: Vec<(String, usize)>,
+    pub registers: Vec<String>,
+    pub instructions: Vec<(String, Option<usize>)>,
+}
+
+impl SymbolTable {
+    pub fn new(definition: &ASMDefinition) -> Self {
+        let mut labels: Vec<(String, usize)> = definition
+            .labels
+            .iter()
+            .map(|(name, line)| (name.clone(), *line))
+            .collect();
+        labels.sort();
+        let mut registers: Vec<String> = definition.registers.keys().cloned().collect();
+        registers.sort();
+        let mut instructions: Vec<(String, Option<usize>)> = definition
+            .instructions
+            .iter()
+            .map(|(id, instruction)| {
+                (
+                    definition.symbol_name(*id).to_string(),
+                    instruction.get_arity(),
+                )
+            })
+            .collect();
+        instructions.sort();
+        SymbolTable {
+            labels,
+            registers,
+            instructions,
+        }
+    }
+}
@@ -1,15 +1,20 @@
 use core::fmt;
-use std::{any::Any, ops, result::Result};
+use std::{cmp::Ordering, ops, result::Result};
 
-use crate::asm_definition::ASMDefinition;
+use crate::asm_definition::{ASMDefinition, ArithmeticMode};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ASMValueHolder {
     Int(i32),
+    I8(i8),
+    U8(u8),
+    I64(i64),
     Str(String),
     Float(f32),
     Register(String),
     Label(String),
+    ResolvedLabel(String, usize),
+    Array(Vec<ASMValue>),
     Invalid,
 }
 
@@ -17,11 +22,25 @@ impl fmt::Display for ASMValueHolder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ASMValueHolder::Int(val) => write!(f, "{}", val),
+            ASMValueHolder::I8(val) => write!(f, "{}", val),
+            ASMValueHolder::U8(val) => write!(f, "{}", val),
+            ASMValueHolder::I64(val) => write!(f, "{}", val),
             ASMValueHolder::Str(val) => write!(f, "{}", val),
             ASMValueHolder::Float(val) => write!(f, "{}", val),
             ASMValueHolder::Register(reference) => write!(f, "{}", reference),
             ASMValueHolder::Invalid => write!(f, "NIL"),
             ASMValueHolder::Label(val) => write!(f, "{}", val),
+            ASMValueHolder::ResolvedLabel(val, _) => write!(f, "{}", val),
+            ASMValueHolder::Array(values) => {
+                write!(f, "[")?;
+                for (position, value) in values.iter().enumerate() {
+                    if position > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
@@ -32,6 +51,12 @@ pub struct ASMValue {
     value: ASMValueHolder,
 }
 
+impl PartialEq for ASMValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
 impl fmt::Display for ASMValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.value)
@@ -39,6 +64,13 @@ impl fmt::Display for ASMValue {
 }
 
 impl ASMValue {
+    pub fn new_from_holder(_value: ASMValueHolder, ldef: Option<*mut ASMDefinition>) -> Self {
+        ASMValue {
+            lang_definiton: ldef,
+            value: _value,
+        }
+    }
+
     pub fn new_int(_value: i32, ldef: Option<*mut ASMDefinition>) -> Self {
         ASMValue {
             lang_definiton: ldef,
@@ -46,6 +78,13 @@ impl ASMValue {
         }
     }
 
+    pub fn new_str(_value: String, ldef: Option<*mut ASMDefinition>) -> Self {
+        ASMValue {
+            lang_definiton: ldef,
+            value: ASMValueHolder::Str(_value),
+        }
+    }
+
     pub fn new_label(_value: String, ldef: Option<*mut ASMDefinition>) -> Self {
         ASMValue {
             lang_definiton: ldef,
@@ -79,6 +118,235 @@ impl ASMValue {
         }
     }
 
+    pub fn concat(&self, other: &ASMValue) -> Result<ASMValue, &str> {
+        match (self.concat_part(), other.concat_part()) {
+            (Some(left), Some(right)) => Ok(ASMValue::new_str(left + &right, self.lang_definiton)),
+            _ => Err("Only strings and numbers can be concatenated!"),
+        }
+    }
+
+    /// Zero numbers of every width, the empty string, the empty array and `Invalid` are
+    /// falsy; every other value is truthy. Labels and register references are always
+    /// truthy, so registers should be resolved before asking.
+    pub fn is_truthy(&self) -> bool {
+        match &self.value {
+            ASMValueHolder::Int(value) => *value != 0,
+            ASMValueHolder::I8(value) => *value != 0,
+            ASMValueHolder::U8(value) => *value != 0,
+            ASMValueHolder::I64(value) => *value != 0,
+            ASMValueHolder::Float(value) => *value != 0.0,
+            ASMValueHolder::Str(value) => !value.is_empty(),
+            ASMValueHolder::Array(values) => !values.is_empty(),
+            ASMValueHolder::Invalid => false,
+            ASMValueHolder::Register(_)
+            | ASMValueHolder::Label(_)
+            | ASMValueHolder::ResolvedLabel(_, _) => true,
+        }
+    }
+
+    pub fn to_float(&self) -> Result<ASMValue, &str> {
+        match self.as_f64() {
+            Some(value) => Ok(ASMValue::new_from_holder(
+                ASMValueHolder::Float(value as f32),
+                self.lang_definiton,
+            )),
+            None => Err("Only numbers can be converted to a float!"),
+        }
+    }
+
+    /// Converts a number to an `Int`, truncating floats towards zero. Values that do
+    /// not fit in an `i32`, and NaN, are errors rather than being saturated.
+    pub fn to_int(&self) -> Result<ASMValue, &str> {
+        let value = match (self.as_i64(), &self.value) {
+            (Some(value), _) => value as f64,
+            (None, ASMValueHolder::Float(value)) => (*value as f64).trunc(),
+            _ => return Err("Only numbers can be converted to an integer!"),
+        };
+        if value.is_nan() || value < i32::MIN as f64 || value > i32::MAX as f64 {
+            return Err("Number does not fit in an integer!");
+        }
+        Ok(ASMValue::new_int(value as i32, self.lang_definiton))
+    }
+
+    pub fn to_str(&self) -> Result<ASMValue, &str> {
+        match self.concat_part() {
+            Some(value) => Ok(ASMValue::new_str(value, self.lang_definiton)),
+            None => Err("Only strings and numbers can be converted to a string!"),
+        }
+    }
+
+    pub fn char_len(&self) -> Result<usize, &str> {
+        match &self.value {
+            ASMValueHolder::Str(value) => Ok(value.chars().count()),
+            _ => Err("Only strings have a length!"),
+        }
+    }
+
+    pub fn substring(&self, start: usize, length: usize) -> Result<ASMValue, &str> {
+        match &self.value {
+            ASMValueHolder::Str(value) => Ok(ASMValue::new_str(
+                value.chars().skip(start).take(length).collect(),
+                self.lang_definiton,
+            )),
+            _ => Err("Only strings can be sliced!"),
+        }
+    }
+
+    pub fn element(&self, index: usize) -> Result<ASMValue, &str> {
+        match &self.value {
+            ASMValueHolder::Array(values) => values.get(index).cloned(),
+            ASMValueHolder::Str(value) => value
+                .chars()
+                .nth(index)
+                .map(|c| ASMValue::new_str(c.to_string(), self.lang_definiton)),
+            _ => return Err("Only arrays and strings can be indexed!"),
+        }
+        .ok_or("Index out of range!")
+    }
+
+    fn concat_part(&self) -> Option<String> {
+        match &self.value {
+            ASMValueHolder::Str(value) => Some(value.clone()),
+            ASMValueHolder::Int(_)
+            | ASMValueHolder::I8(_)
+            | ASMValueHolder::U8(_)
+            | ASMValueHolder::I64(_)
+            | ASMValueHolder::Float(_) => Some(self.value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Orders numbers of any width against each other and strings against strings.
+    /// Returns `None` for other combinations and whenever either side is NaN, so every
+    /// comparison method below is false for NaN, following IEEE 754.
+    pub fn compare(&self, other: &ASMValue) -> Option<Ordering> {
+        if let (ASMValueHolder::Str(left), ASMValueHolder::Str(right)) = (&self.value, &other.value)
+        {
+            return Some(left.cmp(right));
+        }
+        if let (Some(left), Some(right)) = (self.as_i64(), other.as_i64()) {
+            return Some(left.cmp(&right));
+        }
+        match (self.as_f64(), other.as_f64()) {
+            (Some(left), Some(right)) => left.partial_cmp(&right),
+            _ => None,
+        }
+    }
+
+    pub fn equals(&self, other: &ASMValue) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+
+    pub fn less_than(&self, other: &ASMValue) -> bool {
+        self.compare(other) == Some(Ordering::Less)
+    }
+
+    pub fn less_equal(&self, other: &ASMValue) -> bool {
+        matches!(self.compare(other), Some(Ordering::Less | Ordering::Equal))
+    }
+
+    pub fn greater_than(&self, other: &ASMValue) -> bool {
+        self.compare(other) == Some(Ordering::Greater)
+    }
+
+    pub fn greater_equal(&self, other: &ASMValue) -> bool {
+        matches!(
+            self.compare(other),
+            Some(Ordering::Greater | Ordering::Equal)
+        )
+    }
+
+    pub fn offset(&self, delta: i64, mode: ArithmeticMode) -> Result<ASMValue, &str> {
+        let value = match self.as_i64() {
+            Some(value) => value as i128,
+            None => return Err("Only integers can be offset!"),
+        };
+        self.fit_integer(value + delta as i128, mode)
+    }
+
+    pub fn assign_to(
+        &self,
+        target: &ASMValue,
+        mode: ArithmeticMode,
+    ) -> Result<ASMValue, &'static str> {
+        match (self.as_i64(), target.integer_bounds()) {
+            (Some(value), Some(_)) => target.fit_integer(value as i128, mode),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    fn integer_bounds(&self) -> Option<(i128, i128)> {
+        match self.value {
+            ASMValueHolder::Int(_) => Some((i32::MIN as i128, i32::MAX as i128)),
+            ASMValueHolder::I8(_) => Some((i8::MIN as i128, i8::MAX as i128)),
+            ASMValueHolder::U8(_) => Some((u8::MIN as i128, u8::MAX as i128)),
+            ASMValueHolder::I64(_) => Some((i64::MIN as i128, i64::MAX as i128)),
+            _ => None,
+        }
+    }
+
+    fn fit_integer(&self, value: i128, mode: ArithmeticMode) -> Result<ASMValue, &'static str> {
+        let (min, max) = match self.integer_bounds() {
+            Some(bounds) => bounds,
+            None => return Err("Only integers have a width!"),
+        };
+        let value = match mode {
+            ArithmeticMode::Wrapping => min + (value - min).rem_euclid(max - min + 1),
+            ArithmeticMode::Saturating => value.clamp(min, max),
+            ArithmeticMode::Checked if value < min || value > max => {
+                return Err("Integer overflow!")
+            }
+            ArithmeticMode::Checked => value,
+        };
+        let holder = match self.value {
+            ASMValueHolder::Int(_) => ASMValueHolder::Int(value as i32),
+            ASMValueHolder::I8(_) => ASMValueHolder::I8(value as i8),
+            ASMValueHolder::U8(_) => ASMValueHolder::U8(value as u8),
+            _ => ASMValueHolder::I64(value as i64),
+        };
+        Ok(ASMValue::new_from_holder(holder, self.lang_definiton))
+    }
+
+    /// Adds two integers in the width of `self`, overflowing by the definition's
+    /// arithmetic mode. A checked overflow raises an exception and yields `Invalid`.
+    fn add_integer(&self, rhs: &ASMValue) -> ASMValue {
+        let (left, right) = match (self.as_i64(), rhs.as_i64()) {
+            (Some(left), Some(right)) => (left as i128, right as i128),
+            _ => return ASMValue::new_empty(self.lang_definiton),
+        };
+        let definition = self.lang_definiton.or(rhs.lang_definiton);
+        let mode = match definition {
+            Some(ptr) => unsafe { (*ptr).arithmetic_mode() },
+            None => ArithmeticMode::Checked,
+        };
+        match self.fit_integer(left + right, mode) {
+            Ok(value) => value,
+            Err(message) => {
+                if let Some(ptr) = definition {
+                    unsafe { (*ptr).raise_exception(message, true) };
+                }
+                ASMValue::new_empty(self.lang_definiton)
+            }
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self.value {
+            ASMValueHolder::Int(value) => Some(value as i64),
+            ASMValueHolder::I8(value) => Some(value as i64),
+            ASMValueHolder::U8(value) => Some(value as i64),
+            ASMValueHolder::I64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self.value {
+            ASMValueHolder::Float(value) => Some(value as f64),
+            _ => self.as_i64().map(|value| value as f64),
+        }
+    }
+
     pub fn get_lang_definition(&self) -> Option<*mut ASMDefinition> {
         self.lang_definiton
     }
@@ -86,11 +354,20 @@ impl ASMValue {
     pub fn resolve(&self) -> ASMValue {
         match self.value {
             ASMValueHolder::Int(_) => self.clone(),
+            ASMValueHolder::I8(_) => self.clone(),
+            ASMValueHolder::U8(_) => self.clone(),
+            ASMValueHolder::I64(_) => self.clone(),
             ASMValueHolder::Str(_) => self.clone(),
             ASMValueHolder::Float(_) => self.clone(),
-            ASMValueHolder::Register(_) => self.try_resolve_register().unwrap(),
+            ASMValueHolder::Register(_) => {
+                let mut value = self.try_resolve_register().unwrap();
+                value.lang_definiton = value.lang_definiton.or(self.lang_definiton);
+                value
+            }
             ASMValueHolder::Invalid => self.clone(),
             ASMValueHolder::Label(_) => self.clone(),
+            ASMValueHolder::ResolvedLabel(_, _) => self.clone(),
+            ASMValueHolder::Array(_) => self.clone(),
         }
     }
 
@@ -109,12 +386,18 @@ impl ASMValue {
         Err("ASMValue wasnt a reference!")
     }
 
+    /// Writes `value` to the referenced register through `set_register`, so the
+    /// definition's register assign mode applies to instruction results too.
     pub fn try_modify_register(&self, value: ASMValue) {
         if let ASMValueHolder::Register(reference) = self.value.clone() {
             match self.lang_definiton {
                 Some(ptr) => unsafe {
                     let definition = &mut *ptr as &mut ASMDefinition;
-                    definition.registers.insert(reference, value.clone());
+                    if !definition.has_register(&reference) {
+                        definition.registers.insert(reference, value);
+                    } else if let Err(error) = definition.set_register(&reference, value) {
+                        definition.raise_error(error, true);
+                    }
                 },
                 None => {
                     println!("Failed to modify state of {}", reference);
@@ -136,18 +419,17 @@ impl ops::Add<ASMValue> for ASMValue {
     type Output = ASMValue;
     fn add(self, rhs: ASMValue) -> Self::Output {
         match self.value {
-            ASMValueHolder::Int(lvalue) => {
-                if let ASMValueHolder::Int(rvalue) = rhs.value {
-                    ASMValue::new_int(rvalue + lvalue, self.lang_definiton)
-                } else {
-                    ASMValue::new_empty(self.lang_definiton)
-                }
-            }
+            ASMValueHolder::Int(_)
+            | ASMValueHolder::I8(_)
+            | ASMValueHolder::U8(_)
+            | ASMValueHolder::I64(_) => self.add_integer(&rhs),
             ASMValueHolder::Str(_) => todo!(),
             ASMValueHolder::Float(_) => todo!(),
             ASMValueHolder::Invalid => ASMValue::new_empty(self.lang_definiton),
             ASMValueHolder::Register(_) => todo!(),
             ASMValueHolder::Label(_) => todo!(),
+            ASMValueHolder::ResolvedLabel(_, _) => ASMValue::new_empty(self.lang_definiton),
+            ASMValueHolder::Array(_) => ASMValue::new_empty(self.lang_definiton),
         }
     }
 }
@@ -1,7 +1,15 @@
+pub mod asm_ast_debug;
+pub mod asm_compiled;
 pub mod asm_definition;
+pub mod asm_error;
 pub mod asm_instruction;
+pub mod asm_interner;
+pub mod asm_lexer;
+pub mod asm_listing;
+pub mod asm_program;
+pub mod asm_reachability;
+pub mod asm_stdlib;
+pub mod asm_symbol_table;
 pub mod asm_value;
 
 use crate::asm_definition::*;
-use crate::asm_instruction::*;
-use crate::asm_value::*;
@@ -13,17 +13,20 @@ JMP loop
 "
     .into();
 
-    ASMDefinition::new()
+    let result = ASMDefinition::new()
         .insert_register("R1")
         .insert_register("R2")
         .insert_register("R3")
-        .insert_instruction("MOV", |state, arg| {
+        .insert_instruction("MOV", |_state, arg| {
             arg[0].try_modify_register(arg[1].resolve())
         })
-        .insert_instruction("ADD", |state, arg| {
+        .insert_instruction("ADD", |_state, arg| {
             arg[0].try_modify_register(arg[0].resolve() + arg[1].resolve())
         })
-        .insert_instruction("PRINT", |state, arg| println!("{}", arg[0].resolve()))
+        .insert_instruction("PRINT", |_state, arg| println!("{}", arg[0].resolve()))
         .insert_instruction("JMP", |state, arg| state.jump_to_label(arg[0].resolve()))
         .interpret(text);
+    if let Err(error) = result {
+        println!("Program exited with an error: {}", error);
+    }
 }
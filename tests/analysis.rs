@@ -0,0 +1,169 @@
+mod common;
+
+use common::{machine, mov, register};
+use riasm::{
+    asm_definition::ASMDefinition,
+    asm_instruction::ControlFlow,
+    asm_listing::ListingLine,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+#[test]
+fn unused_label_is_a_warning() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("unused:\nmov [a] 1".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(definition.error_count(), 0);
+    assert_eq!(definition.warnings().len(), 1);
+    assert_eq!(
+        definition.warnings()[0].to_string(),
+        "label unused is never referenced"
+    );
+}
+
+#[test]
+fn symbol_table_lists_labels_registers_and_instructions() {
+    let mut definition = ASMDefinition::new()
+        .insert_register("a")
+        .insert_instruction_with_arity("mov", 2, mov)
+        .insert_instruction("jmp", |state, args| state.jump_to_label(args[0].clone()));
+    assert!(definition
+        .scan("start:\nmov [a] 1\nend:\njmp start".into())
+        .is_ok());
+    let table = definition.symbol_table();
+    assert_eq!(
+        table.labels,
+        vec![("end".to_string(), 4), ("start".to_string(), 0)]
+    );
+    assert_eq!(table.registers, vec!["a".to_string()]);
+    assert_eq!(
+        table.instructions,
+        vec![("jmp".to_string(), None), ("mov".to_string(), Some(2))]
+    );
+}
+
+#[test]
+fn listing_groups_instructions_with_labels_and_arguments() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\n\nloop:\nadd [a] 2\ngoto loop".into())
+        .ok()
+        .unwrap();
+    let line = |index, source_line, address, label: Option<&str>, mnemonic: &str, args: &[&str]| {
+        ListingLine {
+            index,
+            source_line: Some(source_line),
+            address,
+            label: label.map(str::to_string),
+            mnemonic: mnemonic.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    };
+    assert_eq!(
+        definition.listing(&stream),
+        vec![
+            line(0, 0, 0, None, "mov", &["[a]", "1"]),
+            line(1, 3, 4, Some("loop"), "add", &["[a]", "2"]),
+            line(2, 4, 8, None, "goto", &["loop"]),
+        ]
+    );
+}
+
+#[test]
+fn ast_debug_renders_grouped_expressions() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("loop:\nmov [a] 1\ngoto loop".into())
+        .ok()
+        .unwrap();
+    assert_eq!(
+        definition.ast_debug(&stream),
+        "Expression @0\n  Label loop\n  Instruction mov\n    Register a\n    Value Int(1)\n\
+         Expression @4\n  Instruction goto\n    Value Label(\"loop\")\n"
+    );
+}
+
+const THREE_ERRORS: &str = "bogus 1\nmov [zz] 1\nmov [a] 300i8";
+
+#[test]
+fn collect_all_errors_reports_every_problem() {
+    let mut definition = machine().with_collect_all_errors(true);
+    let errors = definition.scan(THREE_ERRORS.into()).err().unwrap();
+    let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "line 3: 300i8 is not a valid numeric literal",
+            "line 1: bogus is an unknown instruction",
+            "line 2: register zz is not defined",
+        ]
+    );
+    assert!(!definition.is_halted());
+}
+
+#[test]
+fn scan_stops_at_lexer_errors_by_default() {
+    let mut definition = machine();
+    let errors = definition.scan(THREE_ERRORS.into()).err().unwrap();
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn block_after_an_unconditional_jump_is_unreachable() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\ngoto done\nmov [a] 2\nmov [b] 2\ndone:\nhalt".into())
+        .ok()
+        .unwrap();
+    let mut reachable: Vec<usize> = definition.reachable_lines(&stream).into_iter().collect();
+    reachable.sort();
+    assert_eq!(reachable, vec![0, 4, 15]);
+}
+
+fn jmp(state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    state.jump_to_label(args[0].clone());
+}
+
+#[test]
+fn host_jump_follows_its_declared_control_flow() {
+    let mut definition = ASMDefinition::new()
+        .insert_instruction_with_arity("MOV", 2, mov)
+        .insert_instruction_with_arity("JMP", 1, jmp)
+        .insert_register("a")
+        .with_control_flow("JMP", ControlFlow::Jump(0));
+    let stream = definition
+        .scan("MOV [a] 1\nJMP done\nMOV [a] 2\ndone:\nMOV [a] 3".into())
+        .ok()
+        .unwrap();
+    let mut reachable: Vec<usize> = definition.reachable_lines(&stream).into_iter().collect();
+    reachable.sort();
+    assert_eq!(reachable, vec![0, 4, 11]);
+}
+
+#[test]
+fn undeclared_halt_falls_through() {
+    let mut definition = ASMDefinition::new()
+        .insert_instruction_with_arity("mov", 2, mov)
+        .insert_instruction_with_arity("halt", 0, |_, _| {})
+        .insert_register("a");
+    let stream = definition.scan("halt\nmov [a] 1".into()).ok().unwrap();
+    let mut reachable: Vec<usize> = definition.reachable_lines(&stream).into_iter().collect();
+    reachable.sort();
+    assert_eq!(reachable, vec![0, 2]);
+}
+
+#[test]
+fn skipped_branch_is_left_uncovered() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\njump_if [a] eq 1 skip\nmov [b] 2\nskip:\nhalt".into())
+        .ok()
+        .unwrap();
+    definition.run(stream.clone());
+    let mut covered: Vec<usize> = definition.coverage().iter().copied().collect();
+    covered.sort();
+    assert_eq!(covered, vec![0, 4, 14]);
+    assert_eq!(definition.coverage_percent(&stream), 75.0);
+}
@@ -0,0 +1,59 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use riasm::{
+    asm_definition::ASMDefinition,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+pub fn mov(_state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    args[0].try_modify_register(args[1].resolve());
+}
+
+pub fn add(_state: &mut ASMDefinition, args: Vec<ASMValue>) {
+    args[0].try_modify_register(args[0].resolve() + args[1].resolve());
+}
+
+/// A definition with the stdlib, `mov`, `add` and the registers `a`, `b` and `c`.
+pub fn machine() -> ASMDefinition {
+    ASMDefinition::new()
+        .insert_stdlib()
+        .insert_instruction_with_arity("mov", 2, mov)
+        .insert_instruction_with_arity("add", 2, add)
+        .insert_register("a")
+        .insert_register("b")
+        .insert_register("c")
+}
+
+pub fn register(definition: &ASMDefinition, name: &str) -> ASMValueHolder {
+    definition
+        .get_register(name)
+        .expect("register is defined")
+        .get_value_holder()
+}
+
+/// Program output shared between a test and the definition writing to it.
+#[derive(Clone, Default)]
+pub struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).expect("output is UTF-8")
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
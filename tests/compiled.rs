@@ -0,0 +1,103 @@
+mod common;
+
+use common::{machine, register};
+use riasm::{
+    asm_compiled::{CompiledProgram, Operand},
+    asm_definition::CompletionReason,
+    asm_value::ASMValueHolder,
+};
+
+const SUM_TO_TEN: &str = "loop:\ninc [a]\nadd [b] [a]\njump_if [a] lt 10 loop\nhalt";
+
+#[test]
+fn compiled_loop_matches_interpreted_loop() {
+    let mut interpreted = machine();
+    interpreted.interpret_fresh(SUM_TO_TEN.into()).unwrap();
+
+    let mut compiled = machine();
+    let stream = compiled.scan(SUM_TO_TEN.into()).ok().unwrap();
+    let program = compiled.compile(&stream);
+    assert_eq!(compiled.run_compiled(&program), CompletionReason::Halted);
+
+    assert_eq!(register(&interpreted, "b"), ASMValueHolder::Int(55));
+    assert_eq!(
+        interpreted.registers_snapshot(),
+        compiled.registers_snapshot()
+    );
+    assert_eq!(interpreted.cycles_elapsed(), compiled.cycles_elapsed());
+}
+
+#[test]
+fn adding_to_a_resolved_label_is_empty() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("target:\nmov [a] 1\ngoto target".into())
+        .ok()
+        .unwrap();
+    let program = definition.compile(&stream);
+    let label = match &program.opcodes[1].args[0] {
+        Operand::Value(value) => value.clone(),
+        _ => panic!("goto argument is a value"),
+    };
+    assert_eq!(
+        label.get_value_holder(),
+        ASMValueHolder::ResolvedLabel("target".into(), 0)
+    );
+    assert_eq!(
+        (label + 1.into()).get_value_holder(),
+        ASMValueHolder::Invalid
+    );
+}
+
+#[test]
+fn compiled_program_survives_a_byte_round_trip() {
+    let mut direct = machine();
+    let stream = direct.scan(SUM_TO_TEN.into()).ok().unwrap();
+    let program = direct.compile(&stream);
+    assert_eq!(direct.run_compiled(&program), CompletionReason::Halted);
+
+    let bytes = program.to_bytes().unwrap();
+    let mut loaded = CompiledProgram::from_bytes(&bytes).unwrap();
+    let mut reloaded = machine();
+    loaded.bind(&mut reloaded).unwrap();
+    assert_eq!(reloaded.run_compiled(&loaded), CompletionReason::Halted);
+
+    assert_eq!(register(&reloaded, "b"), ASMValueHolder::Int(55));
+    assert_eq!(direct.registers_snapshot(), reloaded.registers_snapshot());
+}
+
+#[test]
+fn deeply_nested_operand_is_rejected() {
+    let mut bytes: Vec<u8> = b"RIASM".to_vec();
+    bytes.push(1);
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(3u32.to_le_bytes());
+    bytes.extend(b"nop");
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(vec![2u8; 2_000_000]);
+    assert!(CompiledProgram::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn compiled_local_labels_jump_to_the_right_opcode() {
+    let source = "1:\ninc [a]\njump_if [a] lt 3 1b\ngoto 1f\nmov [a] 99\n1:\nhalt";
+    let mut definition = machine();
+    let mut stream = definition.scan(source.into()).ok().unwrap();
+    let program = definition.compile(&stream);
+    assert_eq!(definition.run_compiled(&program), CompletionReason::Halted);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+
+    let mut resolved = machine();
+    stream = resolved
+        .scan("loop:\ninc [a]\njump_if [a] lt 3 loop".into())
+        .ok()
+        .unwrap();
+    resolved.resolve_labels(&mut stream).unwrap();
+    let program = resolved.compile(&stream);
+    resolved.run_compiled(&program);
+    assert_eq!(register(&resolved, "a"), ASMValueHolder::Int(3));
+}
@@ -0,0 +1,800 @@
+mod common;
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::{add, machine, mov, register, Output};
+use riasm::{
+    asm_definition::{diff_registers, ASMDefinition, ASTNode, CompletionReason, StepResult},
+    asm_error::ASMError,
+    asm_instruction::ArgKind,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+#[test]
+fn interpret_fresh_isolates_programs() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("first:\nmov [a] 5\nmov [b] 7\nhalt".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(7));
+
+    definition
+        .interpret_fresh("mov [c] 1\nhalt".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(1));
+    assert!(!definition.labels.contains_key("first"));
+    assert!(definition.has_instruction("mov"));
+}
+
+const COUNT_TO_THREE: &str = "loop:\ninc [a]\njump_if [a] lt 3 loop\nhalt";
+
+#[test]
+fn program_compiles_once_and_runs_twice() {
+    let mut definition = machine();
+    let program = definition.program(COUNT_TO_THREE).unwrap();
+    program.run(&mut definition).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+
+    definition.reset();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    program.run(&mut definition).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn relative_jump_forward_skips_instructions() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("jr 2\nmov [a] 1\nmov [b] 1\nhalt".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn relative_jump_backward_repeats_instructions() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("goto start\nmov [b] 2\nhalt\nstart:\nmov [a] 1\njr -3".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+}
+
+#[test]
+fn relative_jump_out_of_range_is_an_error() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("nop\njr -5\nhalt".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Relative jump out of range!");
+}
+
+#[test]
+fn error_count_and_halted_state_are_public() {
+    let mut definition = machine();
+    assert!(definition.interpret_fresh("foo\nbar".into()).is_err());
+    assert_eq!(definition.error_count(), 2);
+
+    let mut definition = machine();
+    definition.interpret_fresh("mov [a] 1".into()).unwrap();
+    assert_eq!(definition.error_count(), 0);
+    assert!(!definition.is_halted());
+    definition.interpret_fresh("halt".into()).unwrap();
+    assert!(definition.is_halted());
+}
+
+#[test]
+fn time_limit_stops_a_busy_loop() {
+    let mut definition = machine().with_time_limit(Duration::from_millis(50));
+    let started = Instant::now();
+    let error = definition
+        .interpret_fresh("loop:\ngoto loop".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Time limit exceeded!");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn closure_jumps_to_label_by_name() {
+    let mut definition = machine().insert_instruction("skip", |state, _args| {
+        if let Err(error) = state.jump_to_label_name("end") {
+            state.raise_error(error, true);
+        }
+    });
+    definition
+        .interpret_fresh("skip\nmov [a] 1\nend:\nmov [b] 1".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+    assert!(definition.jump_to_label_name("missing").is_err());
+}
+
+#[test]
+fn jump_to_next_instruction_neither_skips_nor_repeats() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("goto next\nnext:\ninc [a]\ninc [b]\nhalt".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn backward_jump_lands_on_its_target() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("goto start\nback:\ninc [a]\nhalt\nstart:\ninc [b]\ngoto back".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn run_reports_halt_or_end_of_program() {
+    let mut definition = machine();
+    let stream = definition.scan("nop\nhalt\nnop".into()).ok().unwrap();
+    assert_eq!(definition.run(stream), CompletionReason::Halted);
+
+    let mut definition = machine();
+    let stream = definition.scan("nop\nnop".into()).ok().unwrap();
+    assert_eq!(definition.run(stream), CompletionReason::ReachedEnd);
+}
+
+#[test]
+fn require_halt_rejects_running_off_the_end() {
+    let mut definition = machine().with_require_halt(true);
+    let error = definition.interpret_fresh("nop".into()).unwrap_err();
+    assert_eq!(error.to_string(), "Program ended without an explicit halt");
+    definition.interpret_fresh("nop\nhalt".into()).unwrap();
+}
+
+#[test]
+fn closure_instruction_captures_host_state() {
+    let counter = Rc::new(Cell::new(0));
+    let captured = Rc::clone(&counter);
+    let mut definition = machine().insert_closure(
+        "tick",
+        Box::new(move |_state, _args| captured.set(captured.get() + 1)),
+    );
+    definition
+        .interpret_fresh("tick\ntick\ntick".into())
+        .unwrap();
+    assert_eq!(counter.get(), 3);
+}
+
+#[test]
+fn halt_hook_fires_once_with_the_reason() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&calls);
+    let mut definition = machine();
+    definition.set_halt_hook(Box::new(move |errors, reason| {
+        recorded.borrow_mut().push((errors, reason))
+    }));
+    definition.interpret_fresh("nop\nhalt\nnop".into()).unwrap();
+    assert_eq!(*calls.borrow(), vec![(0, CompletionReason::Halted)]);
+}
+
+#[test]
+fn resolve_arg_reads_registers_and_passes_immediates() {
+    let mut definition = machine();
+    definition.set_register("a", 9.into()).unwrap();
+    assert_eq!(
+        definition.resolve_arg(&ASMValue::new_reg("a".into(), None)),
+        Ok(ASMValueHolder::Int(9))
+    );
+    assert_eq!(
+        definition.resolve_arg(&5.into()),
+        Ok(ASMValueHolder::Int(5))
+    );
+    assert!(definition
+        .resolve_arg(&ASMValue::new_reg("missing".into(), None))
+        .is_err());
+}
+
+#[test]
+fn loops_back_to_a_label_on_the_first_line() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("start:\ninc [a]\njump_if [a] lt 5 start\nexit 0".into())
+        .unwrap();
+    assert_eq!(definition.labels.get("start"), Some(&0));
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+    assert_eq!(definition.exit_code(), Some(0));
+}
+
+#[test]
+fn instruction_hook_fires_only_for_its_instruction() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&seen);
+    let mut definition = machine().with_memory(4);
+    definition.on_instruction(
+        "store",
+        Box::new(move |args| recorded.borrow_mut().push(args[1].to_string())),
+    );
+    definition
+        .interpret_fresh("mov [a] 1\nstore 0 7\nnop\nstore 1 8".into())
+        .unwrap();
+    assert_eq!(*seen.borrow(), vec!["7".to_string(), "8".to_string()]);
+}
+
+#[test]
+fn take_errors_drains_without_touching_state() {
+    let mut definition = machine();
+    assert!(definition
+        .interpret_fresh("mov [a] 4\njr -9".into())
+        .is_err());
+    let errors = definition.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(definition.error_count(), 0);
+    assert!(definition.take_errors().is_empty());
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(4));
+
+    definition.interpret_fresh("nop".into()).unwrap();
+    assert_eq!(definition.error_count(), 0);
+}
+
+#[test]
+fn inserted_label_is_jumpable() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("goto entry\nmov [a] 1\nhalt\nmov [b] 2".into())
+        .ok()
+        .unwrap();
+    definition.insert_label("entry", 9).unwrap();
+    assert!(definition.insert_label("entry", 0).is_err());
+    assert!(definition.insert_label("late", 100).is_err());
+    definition.run(stream);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+}
+
+#[test]
+fn register_diff_after_one_step() {
+    let mut definition = machine();
+    let stream = definition.scan("mov [a] 3\nmov [b] 4".into()).ok().unwrap();
+    let before = definition.registers_snapshot();
+    assert!(definition.step(&stream));
+    let after = definition.registers_snapshot();
+    assert_eq!(
+        diff_registers(&before, &after),
+        vec![("a".to_string(), 0.into(), 3.into())]
+    );
+}
+
+#[test]
+fn registers_start_at_their_default() {
+    let mut definition =
+        machine().insert_register_with_default("d", ASMValue::new_str("unset".into(), None));
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(
+        register(&definition, "d"),
+        ASMValueHolder::Str("unset".into())
+    );
+    definition
+        .interpret_fresh("mov [d] 1\nmov [a] 2".into())
+        .unwrap();
+    definition.reset();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(
+        register(&definition, "d"),
+        ASMValueHolder::Str("unset".into())
+    );
+}
+
+#[test]
+fn debug_output_shows_definition_state() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] 7\nhalt".into())
+        .unwrap();
+    let debug = format!("{:?}", definition);
+    for expected in [
+        "ASMDefinition",
+        "\"a\": \"7\"",
+        "\"mov\"",
+        "current_line",
+        "halted: true",
+        "errors: 0",
+    ] {
+        assert!(
+            debug.contains(expected),
+            "{} is missing {}",
+            debug,
+            expected
+        );
+    }
+}
+
+#[test]
+fn resolved_labels_still_jump() {
+    let mut definition = machine();
+    let mut stream = definition
+        .scan("loop:\ninc [a]\njump_if [a] eq 3 done\ngoto loop\ndone:\nhalt".into())
+        .ok()
+        .unwrap();
+    definition.resolve_labels(&mut stream).unwrap();
+    let arguments: Vec<ASMValueHolder> = stream
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::ASTValue(value) => Some(value.get_value_holder()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        arguments,
+        vec![
+            ASMValueHolder::Label("eq".into()),
+            ASMValueHolder::Int(3),
+            ASMValueHolder::ResolvedLabel("done".into(), 12),
+            ASMValueHolder::ResolvedLabel("loop".into(), 0),
+        ]
+    );
+    assert_eq!(definition.run(stream), CompletionReason::Halted);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn reentrant_run_is_rejected() {
+    let mut definition = machine().insert_instruction("reenter", |state, _args| {
+        assert_eq!(state.run(Vec::new()), CompletionReason::Error);
+    });
+    let error = definition
+        .interpret_fresh("reenter\nmov [a] 1".into())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "run called while the definition is already running"
+    );
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+}
+
+#[test]
+fn cycle_total_sums_executed_instruction_costs() {
+    let mut definition = machine();
+    definition.set_instruction_cost("inc", 2);
+    definition.set_instruction_cost("jump_if", 4);
+    definition
+        .interpret_fresh("loop:\ninc [a]\njump_if [a] lt 3 loop\nhalt".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+    assert_eq!(definition.cycles_elapsed(), 3 * 2 + 3 * 4 + 1);
+}
+
+#[test]
+fn frozen_definition_rejects_changes_but_still_runs() {
+    let mut definition = machine();
+    definition.freeze();
+    assert!(definition.remove_instruction("mov").is_err());
+    assert!(definition.remove_register("a").is_err());
+    assert!(definition.insert_label("extra", 0).is_err());
+    let mut definition = definition.insert_instruction("late", |_, _| {});
+    assert!(!definition.has_instruction("late"));
+
+    definition
+        .interpret_fresh("loop:\ninc [a]\njump_if [a] lt 3 loop".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn halting_instruction_stops_the_program_immediately() {
+    let mut definition = machine().insert_instruction("stop", |state, _| state.halt());
+    definition
+        .interpret_fresh("mov [a] 1\nstop\nmov [b] 2\nmov [c] 3".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(0));
+}
+
+#[test]
+fn backtrace_names_the_calling_labels() {
+    let mut definition = machine().insert_instruction("trace", |state, _| {
+        let trace = ASMValue::new_str(state.backtrace().join(" "), None);
+        state.set_register("c", trace).unwrap();
+    });
+    definition
+        .interpret_fresh(
+            "main:\ncall outer\nhalt\nouter:\nnop\ncall inner\nret\ninner:\ntrace\nret".into(),
+        )
+        .unwrap();
+    assert_eq!(
+        register(&definition, "c"),
+        ASMValueHolder::Str("outer+5 main+3".into())
+    );
+}
+
+#[test]
+fn register_range_creates_numbered_registers() {
+    let mut definition = machine().insert_register_range("r", 8);
+    for index in 0..8 {
+        assert!(definition.has_register(format!("r{}", index).as_str()));
+    }
+    assert!(!definition.has_register("r8"));
+    definition
+        .interpret_fresh("mov [r0] 4\nmov [r7] 5\nadd [r7] [r0]".into())
+        .unwrap();
+    assert_eq!(register(&definition, "r7"), ASMValueHolder::Int(9));
+}
+
+#[test]
+fn has_instruction_is_a_pure_query() {
+    let definition = machine();
+    assert!(definition.has_instruction("mov"));
+    assert!(definition.has_instruction("halt"));
+    assert!(!definition.has_instruction("missing"));
+    assert!(definition.has_register("a"));
+    assert!(!definition.has_register("z"));
+    assert_eq!(definition.error_count(), 0);
+}
+
+const ENTRY_IN_THE_MIDDLE: &str = "helper:\nmov [b] 9\nret\nmain:\nmov [a] 1\ncall helper\nhalt";
+
+#[test]
+fn entry_point_starts_at_a_label_in_the_middle() {
+    let mut definition = machine().with_entry_point("main");
+    definition
+        .interpret_fresh(ENTRY_IN_THE_MIDDLE.into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(9));
+    assert!(definition.warnings().is_empty());
+}
+
+#[test]
+fn first_step_starts_at_the_entry_point() {
+    let mut definition = machine().with_entry_point("main");
+    let stream = definition.scan(ENTRY_IN_THE_MIDDLE.into()).ok().unwrap();
+    assert!(definition.step(&stream));
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+    assert!(definition.step(&stream));
+    assert!(definition.step(&stream));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(9));
+}
+
+fn signed_machine() -> ASMDefinition {
+    machine().insert_instruction_sig("setn", &[ArgKind::Reg, ArgKind::Int], common::mov)
+}
+
+#[test]
+fn signature_accepts_matching_arguments() {
+    let mut definition = signed_machine();
+    definition.interpret_fresh("setn [a] 5".into()).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+}
+
+#[test]
+fn signature_reports_the_mismatched_position() {
+    let mut definition = signed_machine();
+    let error = definition
+        .interpret_fresh("setn [a] \"five\"".into())
+        .unwrap_err();
+    assert_eq!(
+        error,
+        ASMError::ArgumentMismatch {
+            instruction: "setn".into(),
+            position: 1,
+            expected: ArgKind::Int,
+            actual: "Str(\"five\")".into(),
+        }
+    );
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+}
+
+#[test]
+fn appended_file_jumps_to_a_label_from_an_earlier_file() {
+    let mut definition = machine();
+    definition
+        .scan_append("goto entry\nshared:\nmov [b] \"from a\"\nhalt".into())
+        .unwrap();
+    definition
+        .scan_append("entry:\nmov [a] 1\ngoto shared".into())
+        .unwrap();
+    assert_eq!(definition.labels["shared"], 3);
+    assert_eq!(definition.labels["entry"], 9);
+
+    definition
+        .scan("mov [c] \"unrelated\"".into())
+        .ok()
+        .unwrap();
+    let program = definition.appended_program().to_vec();
+    assert_eq!(definition.run(program), CompletionReason::Halted);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(
+        register(&definition, "b"),
+        ASMValueHolder::Str("from a".into())
+    );
+}
+
+#[test]
+fn implicit_registers_are_created_on_first_use() {
+    let mut definition = machine().with_implicit_registers(true);
+    assert!(!definition.has_register("tmp"));
+    definition
+        .interpret_fresh("add [tmp] 2\nadd [tmp] 3".into())
+        .unwrap();
+    assert_eq!(register(&definition, "tmp"), ASMValueHolder::Int(5));
+
+    let mut strict = machine();
+    assert!(strict.interpret_fresh("add [tmp] 2".into()).is_err());
+    assert!(!strict.has_register("tmp"));
+}
+
+#[test]
+fn unknown_register_error_names_the_register_and_line() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\nmov [b] 2\nadd [b] [c]".into())
+        .ok()
+        .unwrap();
+    definition.remove_register("c").unwrap();
+    assert_eq!(definition.run(stream), CompletionReason::Error);
+    let errors = definition.take_errors();
+    assert_eq!(
+        errors.last().unwrap().to_string(),
+        "line 3: register 'c' not defined"
+    );
+}
+
+#[test]
+fn run_until_stops_when_the_predicate_holds() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("loop:\ninc [a]\njump_if [a] lt 100 loop\nmov [b] 1".into())
+        .ok()
+        .unwrap();
+    let result = definition.run_until(&stream, |state| {
+        state.get_register("a").unwrap().get_value_holder() == ASMValueHolder::Int(5)
+    });
+    assert_eq!(result, StepResult::ConditionMet);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+
+    let result = definition.run_until(&stream, |_| false);
+    assert_eq!(result, StepResult::ReachedEnd);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(100));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+}
+
+fn stream_with_stray_end(definition: &mut ASMDefinition) -> Vec<ASTNode> {
+    let mut stream = definition.scan("mov [a] 1\nmov [b] 2".into()).ok().unwrap();
+    stream.insert(4, ASTNode::ASTExprEnd);
+    stream
+}
+
+#[test]
+fn stray_expression_end_is_an_error() {
+    let mut definition = machine();
+    let stream = stream_with_stray_end(&mut definition);
+    assert_eq!(definition.run(stream), CompletionReason::Error);
+    assert_eq!(
+        definition.take_errors()[0].to_string(),
+        "Expression end at 4 encountered with no instruction present"
+    );
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+}
+
+#[test]
+fn stray_expression_end_is_a_warning_when_collecting_errors() {
+    let mut definition = machine().with_collect_all_errors(true);
+    let stream = stream_with_stray_end(&mut definition);
+    definition.run(stream);
+    assert_eq!(definition.error_count(), 0);
+    assert_eq!(definition.warnings().len(), 1);
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+}
+
+#[test]
+fn arg_rewrite_hook_transforms_arguments_before_the_closure() {
+    let output = Output::default();
+    let mut definition = machine()
+        .with_output(Box::new(output.clone()))
+        .insert_instruction("print", |state, args| {
+            let words: Vec<String> = args.iter().map(|arg| arg.resolve().to_string()).collect();
+            state.write_output(format!("{}\n", words.join(" ")).as_str());
+        });
+    definition.set_arg_rewrite_hook(Box::new(|name, args| {
+        if name != "print" {
+            return;
+        }
+        for arg in args.iter_mut() {
+            if let ASMValueHolder::Int(value) = arg.get_value_holder() {
+                *arg = ASMValue::new_int(value * 2, None);
+            }
+        }
+    }));
+    definition
+        .interpret_fresh("mov [a] 5\nprint 1 21 \"s\" [a]".into())
+        .unwrap();
+    assert_eq!(output.text(), "2 42 s 5\n");
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+}
+
+#[test]
+fn stop_handle_cancels_a_run_from_a_hook() {
+    let mut definition = machine();
+    let handle = definition.stop_handle();
+    let calls = Rc::new(Cell::new(0));
+    let seen = calls.clone();
+    definition.on_instruction(
+        "inc",
+        Box::new(move |_| {
+            seen.set(seen.get() + 1);
+            if seen.get() == 5 {
+                handle.stop();
+            }
+        }),
+    );
+    let stream = definition
+        .scan("loop:\ninc [a]\ngoto loop".into())
+        .ok()
+        .unwrap();
+    assert_eq!(definition.run(stream), CompletionReason::Cancelled);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(5));
+}
+
+#[test]
+fn stop_handle_cancels_a_run_from_another_thread() {
+    let mut definition = machine();
+    let handle = definition.stop_handle();
+    let stream = definition
+        .scan("loop:\ninc [a]\ngoto loop".into())
+        .ok()
+        .unwrap();
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        handle.stop();
+    });
+    let started = Instant::now();
+    assert_eq!(definition.run(stream), CompletionReason::Cancelled);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    stopper.join().unwrap();
+}
+
+#[test]
+fn register_banks_keep_separate_values() {
+    let mut definition = machine().with_memory(1);
+    let first = definition.active_bank().to_string();
+    definition.add_register_bank("second").unwrap();
+    assert!(definition.add_register_bank("second").is_err());
+
+    let stream = definition.scan("mov [a] 1\nstore 0 9".into()).ok().unwrap();
+    definition.run(stream);
+    definition.switch_bank("second").unwrap();
+    assert_eq!(definition.active_bank(), "second");
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    definition
+        .set_register("a", ASMValue::new_int(2, None))
+        .unwrap();
+    assert_eq!(
+        definition.read_memory(0).unwrap().get_value_holder(),
+        ASMValueHolder::Int(9)
+    );
+
+    definition.switch_bank(&first).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert!(definition.switch_bank("missing").is_err());
+}
+
+#[test]
+fn loaded_program_runs_twice_from_a_fresh_state() {
+    let mut definition = machine();
+    assert!(definition.run_loaded().is_err());
+    definition
+        .load("loop:\ninc [a]\nadd [b] [a]\njump_if [a] lt 4 loop\nmov [c] \"done\"".into())
+        .unwrap();
+    for _ in 0..2 {
+        definition.run_loaded().unwrap();
+        assert_eq!(register(&definition, "a"), ASMValueHolder::Int(4));
+        assert_eq!(register(&definition, "b"), ASMValueHolder::Int(10));
+        assert_eq!(
+            register(&definition, "c"),
+            ASMValueHolder::Str("done".into())
+        );
+    }
+
+    let errors = definition.load("bogus".into()).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: bogus is an unknown instruction"
+    );
+    definition.run_loaded().unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(4));
+}
+
+#[test]
+fn fallback_handler_runs_unknown_instructions() {
+    let mut definition = machine();
+    definition.set_unknown_instruction_handler(Box::new(|state, name, args| {
+        let value = ASMValue::new_str(format!("{}:{}", name, args.len()), None);
+        state.set_register("c", value).unwrap();
+    }));
+    definition.set_instruction_cost("shout", 4);
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let recorded = seen.clone();
+    definition.on_instruction(
+        "shout",
+        Box::new(move |args| {
+            let values = args.iter().map(|arg| arg.get_value_holder());
+            recorded.borrow_mut().extend(values);
+        }),
+    );
+    definition.set_arg_rewrite_hook(Box::new(|name, args| {
+        if name == "shout" {
+            args.push(ASMValue::new_int(7, None));
+        }
+    }));
+
+    definition
+        .interpret_fresh("mov [a] 1\nshout [a] 2".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "c"),
+        ASMValueHolder::Str("shout:3".into())
+    );
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            ASMValueHolder::Register("a".into()),
+            ASMValueHolder::Int(2),
+            ASMValueHolder::Int(7)
+        ]
+    );
+    assert_eq!(definition.cycles_elapsed(), 1 + 4);
+
+    let mut strict = machine();
+    assert!(strict.interpret_fresh("shout [a] 2".into()).is_err());
+}
+
+#[test]
+fn program_runs_on_a_definition_with_different_instruction_ids() {
+    let mut scanner = machine();
+    let program = scanner.program("mov [a] 4\nmov [a] 6").unwrap();
+
+    let mut runner = ASMDefinition::new()
+        .insert_instruction_with_arity("add", 2, add)
+        .insert_instruction_with_arity("mov", 2, mov)
+        .insert_register("a");
+    program.run(&mut runner).unwrap();
+    assert_eq!(register(&runner, "a"), ASMValueHolder::Int(6));
+}
+
+#[test]
+fn resolving_reports_an_undefined_jump_target() {
+    let mut definition = machine();
+    let mut stream = definition
+        .scan("loop:\ninc [a]\njump_if [a] eq 3 dnoe\ngoto loop\ndone:\nhalt".into())
+        .ok()
+        .unwrap();
+    let errors = definition.resolve_labels(&mut stream).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "line 3: label dnoe is not defined");
+}
+
+#[test]
+fn failed_append_leaves_earlier_labels_alone() {
+    let mut definition = machine();
+    definition
+        .scan_append("shared:\nmov [a] 1\nhalt".into())
+        .unwrap();
+    assert!(definition
+        .scan_append(".const limit 3\nbroken:\nfrobnicate".into())
+        .is_err());
+    let mut labels: Vec<&String> = definition.labels.keys().collect();
+    labels.sort();
+    assert_eq!(labels, vec!["shared"]);
+    assert!(!definition.constants.contains_key("limit"));
+
+    definition
+        .scan_append("broken:\ngoto shared".into())
+        .unwrap();
+    assert_eq!(definition.labels["broken"], 6);
+}
@@ -0,0 +1,644 @@
+mod common;
+
+use std::{io::BufReader, rc::Rc};
+
+use common::{machine, register};
+use riasm::{
+    asm_definition::{ASMDefinition, ASTNode, RegisterSyntax},
+    asm_interner::Symbol,
+    asm_lexer::TokenKind,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+fn lex_argument(definition: &ASMDefinition, word: &str) -> TokenKind {
+    let tokens = definition
+        .lex(format!("mov [a] {}", word).as_str())
+        .expect("argument lexes");
+    tokens[2].kind.clone()
+}
+
+#[test]
+fn all_comment_file_scans_to_nothing() {
+    let mut definition = machine();
+    let stream = definition.scan(";; first\n   ;; second\n/* block */\n".into());
+    assert!(matches!(stream, Ok(stream) if stream.is_empty()));
+    assert_eq!(definition.error_count(), 0);
+}
+
+#[test]
+fn directive_only_file_scans_to_nothing() {
+    let mut definition = machine();
+    let stream = definition.scan(".const LIMIT 5\n.array table 1 2 3\n".into());
+    assert!(matches!(stream, Ok(stream) if stream.is_empty()));
+    assert!(definition.constants.contains_key("LIMIT"));
+    assert!(definition.constants.contains_key("table"));
+}
+
+#[test]
+fn register_sigils_parse_the_same_program() {
+    let mut brackets = machine();
+    brackets
+        .interpret_fresh("mov [b] 2\nmov [a] 4\nadd [a] [b]".into())
+        .unwrap();
+    let mut prefixed = machine().with_register_sigil(RegisterSyntax::Prefix('%'));
+    prefixed
+        .interpret_fresh("mov %b 2\nmov %a 4\nadd %a %b".into())
+        .unwrap();
+    assert_eq!(register(&brackets, "a"), ASMValueHolder::Int(6));
+    assert_eq!(register(&prefixed, "a"), ASMValueHolder::Int(6));
+    assert!(prefixed.scan("mov [a] 1".into()).is_err());
+}
+
+#[test]
+fn literal_suffixes_select_the_value_type() {
+    let definition = machine();
+    let cases = [
+        ("5i8", ASMValueHolder::I8(5)),
+        ("255u8", ASMValueHolder::U8(255)),
+        ("3.0f32", ASMValueHolder::Float(3.0)),
+        ("10i64", ASMValueHolder::I64(10)),
+        ("7i32", ASMValueHolder::Int(7)),
+    ];
+    for (word, expected) in cases {
+        assert_eq!(lex_argument(&definition, word), TokenKind::Number(expected));
+    }
+}
+
+#[test]
+fn literal_out_of_range_for_its_suffix_is_rejected() {
+    let definition = machine();
+    let errors = definition.lex("mov [a] 300i8").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: 300i8 is not a valid numeric literal"
+    );
+}
+
+#[test]
+fn string_literal_spans_two_source_lines() {
+    let mut definition = machine();
+    let tokens = definition.lex("mov [a] \"first\nsecond\"\nhalt").unwrap();
+    assert_eq!(tokens[2].kind, TokenKind::Str("first\nsecond".into()));
+    assert_eq!((tokens[2].first_line, tokens[2].last_line), (0, 1));
+
+    definition
+        .interpret_fresh("mov [a] \"first\nsecond\"\nhalt".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("first\nsecond".into())
+    );
+}
+
+const NUMBERED: &str = "10 inc [a]\n20 jump_if [a] ge 3 end\n30 goto 10\n40 end: halt";
+
+#[test]
+fn numbered_lines_are_jump_targets() {
+    let mut definition = machine().with_line_numbers(true);
+    definition.interpret_fresh(NUMBERED.into()).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn numbered_lines_can_be_scanned_again() {
+    let mut definition = machine().with_line_numbers(true);
+    assert!(definition.scan(NUMBERED.into()).is_ok());
+    assert!(definition.scan(NUMBERED.into()).is_ok());
+}
+
+#[test]
+fn duplicate_line_number_is_rejected() {
+    let mut definition = machine().with_line_numbers(true);
+    let errors = definition.scan("10 nop\n10 halt".into()).err().unwrap();
+    assert_eq!(errors[0].to_string(), "line number 10 is defined twice");
+}
+
+#[test]
+fn lexer_produces_typed_tokens() {
+    let definition = machine();
+    let tokens = definition.lex("loop: mov [a] \"s\" 5\n.const X 1").unwrap();
+    let kinds: Vec<TokenKind> = tokens.iter().map(|token| token.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::LabelDef("loop".into()),
+            TokenKind::Ident("mov".into()),
+            TokenKind::RegRef("a".into()),
+            TokenKind::Str("s".into()),
+            TokenKind::Number(ASMValueHolder::Int(5)),
+            TokenKind::EndOfLine,
+            TokenKind::Directive(".const".into()),
+            TokenKind::Ident("X".into()),
+            TokenKind::Number(ASMValueHolder::Int(1)),
+            TokenKind::EndOfLine,
+        ]
+    );
+    assert_eq!(tokens[0].first_line, 0);
+    assert_eq!(tokens[6].first_line, 1);
+}
+
+#[test]
+fn lexer_reports_errors_with_lines() {
+    let definition = machine();
+    let errors = definition.lex("nop\nmov [a] \"open").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "lines 2-2: string literal is never terminated"
+    );
+}
+
+#[test]
+fn immediate_prefix_forces_a_literal() {
+    let definition = machine().with_immediate_prefix(true);
+    assert_eq!(
+        lex_argument(&definition, "#10"),
+        TokenKind::Number(ASMValueHolder::Int(10))
+    );
+    let errors = definition.lex("mov [a] #loop").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: #loop is not a valid immediate value"
+    );
+    assert!(machine().lex("mov [a] #10").is_err());
+}
+
+#[test]
+fn instruction_names_are_interned_once() {
+    let mut definition = machine();
+    let mov = definition.symbol("mov").unwrap();
+    assert_eq!(definition.intern("mov"), mov);
+    assert_eq!(definition.symbol_name(mov), "mov");
+    assert_eq!(definition.classify_instruction("mov"), Some(mov));
+    assert_eq!(definition.classify_instruction("missing"), None);
+
+    let source = "mov [a] 1\n".repeat(100);
+    let stream = definition.scan(source.clone()).ok().unwrap();
+    let ids: Vec<Symbol> = stream
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::ASTInstruction(id) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ids, vec![mov; 100]);
+
+    definition.interpret_fresh(source).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+}
+
+fn conditional_program(debug: i32) -> String {
+    format!(
+        ".const DEBUG {}\nmov [a] 1\n.if DEBUG\nmov [b] 2\n.endif\nmov [c] 3",
+        debug
+    )
+}
+
+#[test]
+fn if_block_is_included_for_a_truthy_constant() {
+    let mut definition = machine();
+    definition.interpret_fresh(conditional_program(1)).unwrap();
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn if_block_is_skipped_for_a_falsy_constant() {
+    let mut definition = machine();
+    definition.interpret_fresh(conditional_program(0)).unwrap();
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn unmatched_endif_is_a_parse_error() {
+    let mut definition = machine();
+    let errors = definition.scan("nop\n.endif".into()).err().unwrap();
+    assert_eq!(errors[0].to_string(), ".endif without a matching .if");
+}
+
+#[test]
+fn array_constant_is_indexed_at_runtime() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh(
+            ".array table 10 20 30\n.const word \"xyz\"\nmov [a] table@1\nmov [b] word@2".into(),
+        )
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(20));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Str("z".into()));
+}
+
+#[test]
+fn array_index_out_of_range_is_an_error() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh(".array table 10 20 30\nmov [a] table@3".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Index out of range!");
+}
+
+#[test]
+fn underscores_separate_digits_in_literals() {
+    let definition = machine();
+    assert_eq!(
+        lex_argument(&definition, "1_000"),
+        TokenKind::Number(ASMValueHolder::Int(1000))
+    );
+    assert_eq!(
+        lex_argument(&definition, "0xFF_FF"),
+        TokenKind::Number(ASMValueHolder::Int(0xFFFF))
+    );
+    let errors = definition.lex("mov [a] _1000").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: _1000 is not a valid numeric literal"
+    );
+    assert!(definition.lex("mov [a] 1000_").is_err());
+}
+
+#[test]
+fn label_detection_ignores_surrounding_comments() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh(
+            ";; not here:\n  loop:   ;; main loop   \ninc [a]\njump_if [a] lt 3 loop".into(),
+        )
+        .unwrap();
+    let mut labels: Vec<&String> = definition.labels.keys().collect();
+    labels.sort();
+    assert_eq!(labels, vec!["loop"]);
+    assert_eq!(definition.labels["loop"], 0);
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn identical_literals_share_one_pool_entry() {
+    let mut definition = machine();
+    let source = "mov [a] \"hi\"\nmov [b] \"hi\"\nmov [c] 1.5";
+    let stream = definition.scan(source.into()).ok().unwrap();
+    match (&stream[2], &stream[6]) {
+        (ASTNode::ASTPooled(0, first), ASTNode::ASTPooled(0, second)) => {
+            assert!(Rc::ptr_eq(first, second))
+        }
+        _ => panic!("both \"hi\" literals should be pooled"),
+    }
+    assert!(matches!(stream[10], ASTNode::ASTPooled(1, _)));
+    assert_eq!(definition.constant_pool().len(), 2);
+
+    definition.scan(source.into()).ok().unwrap();
+    assert_eq!(definition.constant_pool().len(), 2);
+}
+
+#[test]
+fn program_keeps_its_literals_across_later_scans() {
+    let mut definition = machine();
+    let program = definition.program("mov [a] \"kept\"").unwrap();
+    definition.scan("mov [b] \"other\"".into()).ok().unwrap();
+    program.run(&mut definition).unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("kept".into())
+    );
+}
+
+#[test]
+fn scanned_stream_keeps_its_literals_across_later_scans() {
+    let mut definition = machine();
+    let first = definition.scan("mov [a] \"first\"".into()).ok().unwrap();
+    definition.scan("mov [a] \"second\"".into()).ok().unwrap();
+    definition.run(first);
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("first".into())
+    );
+}
+
+#[test]
+fn empty_and_blank_inputs_scan_to_nothing() {
+    for source in ["", "\n", "  \t \n   \n"] {
+        let mut definition = machine();
+        let stream = definition.scan(source.into());
+        assert!(matches!(stream, Ok(stream) if stream.is_empty()));
+        assert!(definition.interpret_fresh(source.into()).is_ok());
+        assert_eq!(definition.error_count(), 0);
+    }
+}
+
+#[test]
+fn default_radix_reads_unprefixed_literals() {
+    let definition = machine().with_default_radix(16);
+    assert_eq!(
+        lex_argument(&definition, "FF"),
+        TokenKind::Number(ASMValueHolder::Int(255))
+    );
+    assert_eq!(
+        lex_argument(&definition, "beef"),
+        TokenKind::Number(ASMValueHolder::Int(0xBEEF))
+    );
+    assert_eq!(
+        lex_argument(&definition, "0b11"),
+        TokenKind::Number(ASMValueHolder::Int(3))
+    );
+    assert!(definition.lex("mov [a] 1G").is_err());
+}
+
+#[test]
+fn names_that_read_as_numbers_are_rejected() {
+    let mut definition = machine().with_default_radix(16);
+    let errors = definition.scan(".const c 7".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: name c is a valid radix-16 literal"
+    );
+    let errors = definition.scan("face:\ngoto face".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: label face is a valid radix-16 literal"
+    );
+
+    definition
+        .interpret_fresh(".const k 7\nkeep:\nmov [a] k".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(7));
+}
+
+#[test]
+fn block_comments_are_stripped_within_and_across_lines() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] /* inline */ 1\n/* first\nmov [b] 2\nlast */ mov [c] 3".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn nested_block_comments_are_optional() {
+    let source = "/* outer /* inner */ mov [b] 2 */\nmov [a] 1";
+    let mut nested = machine().with_nested_comments(true);
+    nested.interpret_fresh(source.into()).unwrap();
+    assert_eq!(register(&nested, "b"), ASMValueHolder::Int(0));
+    assert_eq!(register(&nested, "a"), ASMValueHolder::Int(1));
+    assert!(machine().scan(source.into()).is_err());
+}
+
+#[test]
+fn unterminated_block_comment_is_a_parse_error() {
+    let mut definition = machine();
+    let errors = definition.scan("nop\n/* open\nnop".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2: block comment is never terminated"
+    );
+}
+
+#[test]
+fn classifying_words_raises_no_errors() {
+    let mut definition = machine();
+    assert_eq!(definition.classify_instruction("nope"), None);
+    assert!(matches!(
+        definition.classify_identifier("somewhere"),
+        ASTNode::ASTValue(value)
+            if value.get_value_holder() == ASMValueHolder::Label("somewhere".into())
+    ));
+    assert_eq!(definition.error_count(), 0);
+
+    assert!(definition.scan("nope".into()).is_err());
+    assert_eq!(definition.error_count(), 1);
+}
+
+#[test]
+fn literal_parser_extends_the_lexer() {
+    let mut definition = machine();
+    definition.set_literal_parser(Box::new(|word| {
+        let (numerator, denominator) = word.split_once('/')?;
+        let value = numerator.parse::<f32>().ok()? / denominator.parse::<f32>().ok()?;
+        Some(ASMValue::new_from_holder(
+            ASMValueHolder::Float(value),
+            None,
+        ))
+    }));
+    assert_eq!(
+        lex_argument(&definition, "1/2"),
+        TokenKind::Number(ASMValueHolder::Float(0.5))
+    );
+    assert_eq!(
+        lex_argument(&definition, "12"),
+        TokenKind::Number(ASMValueHolder::Int(12))
+    );
+    definition.interpret_fresh("mov [a] 3/4".into()).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Float(0.75));
+}
+
+#[test]
+fn identifier_precedence_is_register_then_constant_then_label() {
+    let mut definition = machine().with_bare_registers(true);
+    definition
+        .scan(".const a 7\n.const limit 9\na:\nlimit:\nplain:\nnop".into())
+        .ok()
+        .unwrap();
+    assert!(matches!(
+        definition.classify_identifier("a"),
+        ASTNode::ASTRegister(name) if name == "a"
+    ));
+    assert!(matches!(
+        definition.classify_identifier("limit"),
+        ASTNode::ASTValue(value) if value.get_value_holder() == ASMValueHolder::Int(9)
+    ));
+    assert!(matches!(
+        definition.classify_identifier("plain"),
+        ASTNode::ASTValue(value)
+            if value.get_value_holder() == ASMValueHolder::Label("plain".into())
+    ));
+}
+
+#[test]
+fn number_at_line_start_is_a_parse_error() {
+    let mut definition = machine();
+    let errors = definition.scan("nop\n42 [a]".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2: expected instruction at start of line, found 42"
+    );
+}
+
+#[test]
+fn numeric_labels_resolve_backward_and_forward() {
+    let source = "1:\ninc [a]\njump_if [a] lt 3 1b\ngoto 1f\nmov [a] 99\n\
+                  1:\ninc [b]\njump_if [b] lt 2 1b\n\
+                  0:\ninc [c]\njump_if [c] lt 4 0b\nhalt";
+    for radix in [10, 16] {
+        let mut definition = machine().with_default_radix(radix);
+        definition.interpret_fresh(source.into()).unwrap();
+        assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+        assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+        assert_eq!(register(&definition, "c"), ASMValueHolder::Int(4));
+    }
+}
+
+fn mnemonics(definition: &ASMDefinition, stream: &[ASTNode]) -> Vec<String> {
+    stream
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::ASTInstruction(id) => Some(definition.symbol_name(*id).to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn align_pads_with_nops_to_the_next_multiple() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\n.align 4\nmov [b] 2\n.align 2\nhalt".into())
+        .ok()
+        .unwrap();
+    assert_eq!(
+        mnemonics(&definition, &stream),
+        vec!["mov", "nop", "nop", "nop", "mov", "nop", "halt"]
+    );
+    definition.run(stream);
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+}
+
+#[test]
+fn misspelled_register_is_reported_at_scan_time() {
+    let mut definition = machine();
+    let errors = definition
+        .scan("mov [a] 1\nadd [a] [bb]\nmov [cc] 2".into())
+        .err()
+        .unwrap();
+    let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "line 2: register bb is not defined",
+            "line 3: register cc is not defined"
+        ]
+    );
+    assert!(machine()
+        .with_implicit_registers(true)
+        .scan("add [a] [bb]".into())
+        .is_ok());
+}
+
+#[test]
+fn unicode_letters_form_labels_but_emoji_do_not() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("début:\ninc [a]\njump_if [a] lt 2 début".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(2));
+
+    let errors = definition.lex("mov [a] 😀").unwrap_err();
+    assert_eq!(errors[0].to_string(), "line 1: 😀 is not a valid argument");
+    assert!(definition.lex("de\u{301}but: nop").is_err());
+}
+
+#[test]
+fn line_jump_table_resolves_jumps_like_the_label_map() {
+    let dense = "1 inc [a]\n2 jump_if [a] ge 5 done\n3 goto 1\n4 done: halt";
+    for source in [dense, NUMBERED] {
+        let mut mapped = machine().with_line_numbers(true);
+        mapped.interpret_fresh(source.into()).unwrap();
+        let mut tabled = machine().with_line_numbers(true).with_line_jump_table(true);
+        tabled.interpret_fresh(source.into()).unwrap();
+        assert_eq!(mapped.registers_snapshot(), tabled.registers_snapshot());
+        assert_eq!(mapped.cycles_elapsed(), tabled.cycles_elapsed());
+
+        let mut stepped = machine().with_line_numbers(true).with_line_jump_table(true);
+        let stream = stepped.scan(source.into()).ok().unwrap();
+        while stepped.step(&stream) {}
+        assert_eq!(mapped.registers_snapshot(), stepped.registers_snapshot());
+    }
+}
+
+#[test]
+fn stray_value_at_line_start_is_a_parse_error() {
+    let mut definition = machine();
+    let errors = definition.scan("mov [a] 1\n[a] 2".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2: expected instruction at start of line, found [a]"
+    );
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+}
+
+#[test]
+fn reader_scan_matches_string_scan() {
+    let source = "start: mov [a] \"two\nlines\"\n/* block\ncomment */ inc [b]\n\
+                  jump_if [b] lt 3 start ;; loop\nhalt\n";
+    let mut from_string = machine();
+    let expected = from_string.scan(source.into()).ok().unwrap();
+    let mut from_reader = machine();
+    let scanned = from_reader
+        .scan_reader(BufReader::new(source.as_bytes()))
+        .ok()
+        .unwrap();
+    assert_eq!(
+        from_reader.ast_debug(&scanned),
+        from_string.ast_debug(&expected)
+    );
+    assert_eq!(from_reader.labels, from_string.labels);
+    assert_eq!(from_reader.source_line(7), from_string.source_line(7));
+}
+
+#[test]
+fn punctuation_only_lines_name_the_offending_token() {
+    let cases = [
+        (":", "line 1: : is not a valid label"),
+        ("nop\n  ,  ", "line 2: , is an unknown instruction"),
+    ];
+    for (source, expected) in cases {
+        let mut definition = machine();
+        let errors = definition.scan(source.into()).err().unwrap();
+        assert_eq!(errors[0].to_string(), expected);
+    }
+}
+
+#[test]
+fn second_mnemonic_on_a_line_is_a_parse_error() {
+    let mut definition = machine();
+    let errors = definition.scan("nop\nmov add [a]".into()).err().unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2: instruction add is used as an argument"
+    );
+}
+
+#[test]
+fn overlong_line_is_a_parse_error() {
+    let mut definition = machine().with_max_line_length(12);
+    assert!(definition.scan("mov [a] 1".into()).is_ok());
+    let errors = definition
+        .scan("mov [a] 1\nmov [a] 123456".into())
+        .err()
+        .unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2: line is 14 characters long, at most 12 are allowed"
+    );
+
+    let huge = format!("mov [a] \"{}\"", "x".repeat(100_000));
+    let errors = definition.scan(huge).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: line is 100010 characters long, at most 12 are allowed"
+    );
+}
+
+#[test]
+fn too_many_arguments_is_a_parse_error() {
+    let mut definition = machine().with_max_args_per_instruction(2);
+    assert!(definition.scan("mov [a] 1".into()).is_ok());
+    let errors = definition
+        .scan("jump_if [a] eq 1 end\nend: halt".into())
+        .err()
+        .unwrap();
+    assert_eq!(
+        errors[0].to_string(),
+        "line 1: jump_if has 4 arguments, at most 2 are allowed"
+    );
+}
@@ -0,0 +1,456 @@
+mod common;
+
+use common::{machine, register, Output};
+use riasm::{
+    asm_definition::{ASMDefinition, ArithmeticMode, CompletionReason},
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+#[test]
+fn divmod_writes_quotient_and_remainder() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("divmod [a] [b] 17 5".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(3));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+}
+
+#[test]
+fn divmod_requires_register_destinations() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("divmod 1 [b] 17 5".into())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "divmod expects argument 0 to be a register"
+    );
+}
+
+#[test]
+fn concat_joins_strings() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("concat [a] \"ab\" \"cd\"".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("abcd".into())
+    );
+}
+
+#[test]
+fn concat_formats_numbers_into_strings() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [b] 5\nconcat [a] \"x=\" [b]".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("x=5".into())
+    );
+}
+
+#[test]
+fn concat_rejects_labels() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("concat [a] left right".into())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Only strings and numbers can be concatenated!"
+    );
+}
+
+fn random_sequence(seed: u64) -> Vec<ASMValueHolder> {
+    let mut definition = machine().with_seed(seed);
+    let mut sequence = Vec::new();
+    for _ in 0..5 {
+        definition.interpret_fresh("rand [a] 100".into()).unwrap();
+        sequence.push(register(&definition, "a"));
+    }
+    sequence
+}
+
+#[test]
+fn rand_is_reproducible_for_a_seed() {
+    let sequence = random_sequence(42);
+    assert_eq!(sequence, random_sequence(42));
+    assert_ne!(sequence, random_sequence(43));
+    for value in sequence {
+        assert!(matches!(value, ASMValueHolder::Int(value) if (0..100).contains(&value)));
+    }
+}
+
+#[test]
+fn jmpr_jumps_to_the_address_in_a_register() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [b] 11\njmpr [b]\nmov [a] 1\nmov [c] 1".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn jmpr_rejects_a_non_integer_register() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("mov [b] \"x\"\njmpr [b]".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Invalid value of provided destination!");
+}
+
+#[test]
+fn jump_if_never_jumps_on_nan() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("jump_if [a] eq [a] skip\nmov [b] 1\nskip:\nhalt".into())
+        .ok()
+        .unwrap();
+    let nan = ASMValue::new_from_holder(ASMValueHolder::Float(f32::NAN), None);
+    definition.set_register("a", nan).unwrap();
+    definition.run(stream);
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn len_counts_characters_not_bytes() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [b] \"zażółć\"\nlen [a] [b]".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(6));
+}
+
+#[test]
+fn substr_extracts_characters() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("substr [a] \"zażółć\" 2 3\nsubstr [b] \"abc\" 1 10".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("żół".into())
+    );
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Str("bc".into()));
+}
+
+#[test]
+fn printf_substitutes_arguments() {
+    let output = Output::default();
+    let mut definition = machine().with_output(Box::new(output.clone()));
+    definition
+        .interpret_fresh("mov [a] 3\nmov [b] 4\nprintf \"x = %d, y = %d\" [a] [b]".into())
+        .unwrap();
+    assert_eq!(output.text(), "x = 3, y = 4");
+}
+
+#[test]
+fn printf_rejects_an_argument_count_mismatch() {
+    let output = Output::default();
+    let mut definition = machine().with_output(Box::new(output.clone()));
+    let error = definition
+        .interpret_fresh("printf \"%d and %d\" 1".into())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "printf format expects 2 arguments, got 1"
+    );
+    assert_eq!(output.text(), "");
+}
+
+fn cells(definition: &ASMDefinition) -> Vec<ASMValueHolder> {
+    definition
+        .memory()
+        .iter()
+        .map(|cell| cell.get_value_holder())
+        .collect()
+}
+
+const THREE_CELLS: &str = "store 0 1\nstore 1 2\nstore 2 3\n";
+
+#[test]
+fn reset_clears_memory() {
+    let mut definition = machine().with_memory(3);
+    definition.interpret_fresh(THREE_CELLS.into()).unwrap();
+    definition.reset();
+    assert_eq!(cells(&definition), vec![ASMValueHolder::Int(0); 3]);
+
+    definition.interpret_fresh("store 1 5".into()).unwrap();
+    definition.interpret_fresh("load [a] 1".into()).unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(definition.memory().len(), 3);
+}
+
+#[test]
+fn memcpy_copies_a_disjoint_region() {
+    let mut definition = machine().with_memory(6);
+    definition
+        .interpret_fresh(format!("{}memcpy 3 0 3", THREE_CELLS))
+        .unwrap();
+    let expected: Vec<ASMValueHolder> = [1, 2, 3, 1, 2, 3]
+        .iter()
+        .map(|value| ASMValueHolder::Int(*value))
+        .collect();
+    assert_eq!(cells(&definition), expected);
+}
+
+#[test]
+fn memcpy_copies_forward_over_its_own_source() {
+    let mut definition = machine().with_memory(4);
+    definition
+        .interpret_fresh(format!("{}memcpy 1 0 3", THREE_CELLS))
+        .unwrap();
+    let expected: Vec<ASMValueHolder> = [1, 1, 2, 3]
+        .iter()
+        .map(|value| ASMValueHolder::Int(*value))
+        .collect();
+    assert_eq!(cells(&definition), expected);
+}
+
+#[test]
+fn swap_exchanges_two_registers() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] 1\nmov [b] \"two\"\nmov [c] 3\nswap [a] [b]\nswap [c] [c]".into())
+        .unwrap();
+    assert_eq!(
+        register(&definition, "a"),
+        ASMValueHolder::Str("two".into())
+    );
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(1));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn inc_and_dec_step_a_register() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] 5\ninc [a]\ninc [a]\nmov [b] 5\ndec [b]".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(7));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(4));
+}
+
+#[test]
+fn inc_wraps_at_the_int_limit_in_wrapping_mode() {
+    let mut definition = machine().with_arithmetic_mode(ArithmeticMode::Wrapping);
+    definition
+        .interpret_fresh(format!("mov [a] {}\ninc [a]", i32::MAX))
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(i32::MIN));
+}
+
+#[test]
+fn inc_rejects_a_label_valued_register() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("target:\nmov [a] target\ninc [a]".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "inc: Only integers can be offset!");
+}
+
+#[test]
+fn dumpmem_writes_a_clamped_range() {
+    let output = Output::default();
+    let mut definition = machine()
+        .with_memory(4)
+        .with_output(Box::new(output.clone()));
+    definition
+        .interpret_fresh("store 2 7\nstore 3 \"x\"\ndumpmem 1 10".into())
+        .unwrap();
+    assert_eq!(output.text(), "0x0001: 0\n0x0002: 7\n0x0003: x\n");
+    let dump: Vec<ASMValueHolder> = definition
+        .dump_memory(2, 2)
+        .iter()
+        .map(|cell| cell.get_value_holder())
+        .collect();
+    assert_eq!(
+        dump,
+        vec![ASMValueHolder::Int(7), ASMValueHolder::Str("x".into())]
+    );
+    assert!(definition.dump_memory(9, 2).is_empty());
+}
+
+fn branch_on(left: i32, right: i32) -> ASMValueHolder {
+    let mut definition = machine();
+    definition
+        .interpret_fresh(format!(
+            "cmp {} {}\njlt less\njeq equal\njgt greater\nhalt\n\
+             less:\nmov [a] \"less\"\nhalt\n\
+             equal:\nmov [a] \"equal\"\nhalt\n\
+             greater:\nmov [a] \"greater\"\nhalt",
+            left, right
+        ))
+        .unwrap();
+    register(&definition, "a")
+}
+
+#[test]
+fn cmp_drives_the_conditional_jumps() {
+    assert_eq!(branch_on(1, 2), ASMValueHolder::Str("less".into()));
+    assert_eq!(branch_on(2, 2), ASMValueHolder::Str("equal".into()));
+    assert_eq!(branch_on(3, 2), ASMValueHolder::Str("greater".into()));
+}
+
+#[test]
+fn raw_instruction_receives_the_operand_text() {
+    let output = Output::default();
+    let mut definition = machine()
+        .with_output(Box::new(output.clone()))
+        .insert_raw_instruction("echo", |state, args| {
+            state.write_output(format!("{}|", args[0]).as_str())
+        });
+    definition
+        .interpret_fresh("echo hello,   [a] \"world\" 5 ;; note\nmov [a] 1".into())
+        .unwrap();
+    assert_eq!(output.text(), "hello,   [a] \"world\" 5|");
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+}
+
+#[test]
+fn getpc_captures_its_own_address_for_a_later_jump() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh(
+            "mov [a] 1\ngetpc [c]\ninc [b]\njump_if [b] ge 3 done\njmpr [c]\ndone:\nhalt".into(),
+        )
+        .unwrap();
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(4));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(3));
+}
+
+#[test]
+fn instruction_documentation_is_retrievable() {
+    let output = Output::default();
+    let mut definition = machine()
+        .with_output(Box::new(output.clone()))
+        .insert_instruction_doc(
+            "double",
+            |_, args| args[0].try_modify_register(args[0].resolve() + args[0].resolve()),
+            "Doubles a register in place",
+            "double [reg]",
+        );
+    let doc = definition.instruction_doc("double").unwrap();
+    assert_eq!(doc.description, "Doubles a register in place");
+    assert_eq!(doc.usage, "double [reg]");
+    assert!(definition.instruction_doc("mov").is_none());
+
+    definition
+        .interpret_fresh("help double\nhelp mov".into())
+        .unwrap();
+    assert_eq!(
+        output.text(),
+        "double [reg]: Doubles a register in place\nmov: no documentation\n"
+    );
+}
+
+#[test]
+fn exit_halts_with_a_code() {
+    let mut definition = machine();
+    let stream = definition
+        .scan("mov [a] 1\nexit 2\nmov [a] 3".into())
+        .ok()
+        .unwrap();
+    assert_eq!(definition.exit_code(), None);
+    assert_eq!(definition.run(stream.clone()), CompletionReason::Halted);
+    assert_eq!(definition.exit_code(), Some(2));
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(1));
+    assert!(!definition.reachable_lines(&stream).contains(&7));
+}
+
+fn with_default_d() -> ASMDefinition {
+    machine().insert_register_with_default("d", ASMValue::new_int(7, None))
+}
+
+#[test]
+fn clear_resets_one_register_to_its_default() {
+    let mut definition = with_default_d();
+    definition
+        .interpret_fresh("mov [a] 5\nmov [b] 6\nmov [d] 1\nclear [a]\nclear [d]".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(6));
+    assert_eq!(register(&definition, "d"), ASMValueHolder::Int(7));
+}
+
+#[test]
+fn clear_all_keeps_labels_and_the_program_counter() {
+    let mut definition = with_default_d();
+    definition
+        .interpret_fresh("start:\ninc [c]\nmov [a] 5\nmov [d] 1\nclear_all\nmov [b] 2".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(2));
+    assert_eq!(register(&definition, "c"), ASMValueHolder::Int(0));
+    assert_eq!(register(&definition, "d"), ASMValueHolder::Int(7));
+    assert_eq!(definition.labels["start"], 0);
+}
+
+#[test]
+fn conversions_change_the_register_type() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] 3\nitof [a]\nmov [b] -2.75\nftoi [b]\ntostr [c] 1.5".into())
+        .unwrap();
+    assert_eq!(register(&definition, "a"), ASMValueHolder::Float(3.0));
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(-2));
+    assert_eq!(
+        register(&definition, "c"),
+        ASMValueHolder::Str("1.5".into())
+    );
+}
+
+#[test]
+fn converting_an_incompatible_value_is_an_error() {
+    let mut definition = machine();
+    let error = definition
+        .interpret_fresh("mov [a] \"text\"\nitof [a]".into())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "itof: Only numbers can be converted to a float!"
+    );
+}
+
+#[test]
+fn fill_writes_consecutive_cells() {
+    let mut definition = machine().with_memory(5);
+    definition.interpret_fresh("fill 1 3 8".into()).unwrap();
+    let expected: Vec<ASMValueHolder> = [0, 8, 8, 8, 0]
+        .iter()
+        .map(|value| ASMValueHolder::Int(*value))
+        .collect();
+    assert_eq!(cells(&definition), expected);
+}
+
+#[test]
+fn out_of_bounds_fill_writes_nothing() {
+    let mut definition = machine().with_memory(4);
+    let error = definition
+        .interpret_fresh("store 3 1\nfill 2 3 8".into())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "memory range 2..5 is out of bounds");
+    let expected: Vec<ASMValueHolder> = [0, 0, 0, 1]
+        .iter()
+        .map(|value| ASMValueHolder::Int(*value))
+        .collect();
+    assert_eq!(cells(&definition), expected);
+}
+
+#[test]
+fn cmov_writes_only_when_the_condition_holds() {
+    let mut definition = machine();
+    definition
+        .interpret_fresh("mov [a] 1\ncmov [a] [b] 5\ncmov [c] [b] 6\ncmov \"\" [b] 7".into())
+        .unwrap();
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(5));
+
+    definition
+        .interpret_fresh("mov [b] 3\ncmov 0 [b] 9".into())
+        .unwrap();
+    assert_eq!(register(&definition, "b"), ASMValueHolder::Int(3));
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use common::{machine, register};
+use riasm::{
+    asm_definition::{ASMDefinition, ArithmeticMode},
+    asm_error::ASMError,
+    asm_value::{ASMValue, ASMValueHolder},
+};
+
+#[test]
+fn values_compare_by_held_data() {
+    let mut definition = ASMDefinition::new();
+    let pointer: *mut ASMDefinition = &mut definition;
+    assert_eq!(ASMValue::new_int(5, Some(pointer)), 5.into());
+    assert_ne!(ASMValue::new_int(5, None), 6.into());
+    assert_eq!(
+        ASMValue::new_str("ab".into(), None),
+        ASMValue::new_str("ab".into(), None)
+    );
+    assert_ne!(ASMValue::new_reg("a".into(), None), 5.into());
+    assert_ne!(ASMValueHolder::Int(1), ASMValueHolder::I64(1));
+}
+
+#[test]
+fn values_debug_shows_held_data() {
+    let value = ASMValue::new_int(42, None);
+    assert!(format!("{:?}", value).contains("Int(42)"));
+}
+
+#[test]
+fn nan_comparisons_are_false() {
+    let nan = ASMValue::new_from_holder(ASMValueHolder::Float(f32::NAN), None);
+    let one = ASMValue::new_from_holder(ASMValueHolder::Float(1.0), None);
+    for (left, right) in [(&nan, &one), (&one, &nan), (&nan, &nan)] {
+        assert!(!left.equals(right));
+        assert!(!left.less_than(right));
+        assert!(!left.less_equal(right));
+        assert!(!left.greater_than(right));
+        assert!(!left.greater_equal(right));
+        assert_eq!(left.compare(right), None);
+    }
+}
+
+#[test]
+fn adding_to_an_array_is_empty() {
+    let array = ASMValue::new_from_holder(ASMValueHolder::Array(vec![1.into()]), None);
+    assert_eq!(
+        (array + 1.into()).get_value_holder(),
+        ASMValueHolder::Invalid
+    );
+}
+
+fn store_300_in_i8(mode: ArithmeticMode) -> (Result<(), ASMError>, ASMValueHolder) {
+    let mut definition = machine()
+        .with_memory(1)
+        .with_register_assign_mode(mode)
+        .insert_register_with_default("n", ASMValue::new_from_holder(ASMValueHolder::I8(0), None));
+    let result = definition.interpret_fresh("store 0 300\nload [n] 0".into());
+    (result, register(&definition, "n"))
+}
+
+#[test]
+fn checked_assignment_rejects_an_out_of_range_value() {
+    let (result, value) = store_300_in_i8(ArithmeticMode::Checked);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "value 300 does not fit in register n"
+    );
+    assert_eq!(value, ASMValueHolder::I8(0));
+}
+
+#[test]
+fn saturating_assignment_clamps_to_the_register_width() {
+    let (result, value) = store_300_in_i8(ArithmeticMode::Saturating);
+    assert!(result.is_ok());
+    assert_eq!(value, ASMValueHolder::I8(127));
+}
+
+#[test]
+fn wrapping_assignment_truncates_to_the_register_width() {
+    let (result, value) = store_300_in_i8(ArithmeticMode::Wrapping);
+    assert!(result.is_ok());
+    assert_eq!(value, ASMValueHolder::I8(44));
+}
+
+fn truthy(holder: ASMValueHolder) -> bool {
+    ASMValue::new_from_holder(holder, None).is_truthy()
+}
+
+#[test]
+fn numbers_are_truthy_unless_zero() {
+    assert!(truthy(ASMValueHolder::Int(-1)));
+    assert!(!truthy(ASMValueHolder::Int(0)));
+    assert!(truthy(ASMValueHolder::I8(1)));
+    assert!(!truthy(ASMValueHolder::I8(0)));
+    assert!(truthy(ASMValueHolder::U8(255)));
+    assert!(!truthy(ASMValueHolder::U8(0)));
+    assert!(truthy(ASMValueHolder::I64(i64::MIN)));
+    assert!(!truthy(ASMValueHolder::I64(0)));
+    assert!(truthy(ASMValueHolder::Float(0.5)));
+    assert!(!truthy(ASMValueHolder::Float(0.0)));
+    assert!(!truthy(ASMValueHolder::Float(-0.0)));
+}
+
+#[test]
+fn collections_are_truthy_unless_empty() {
+    assert!(truthy(ASMValueHolder::Str("0".into())));
+    assert!(!truthy(ASMValueHolder::Str(String::new())));
+    assert!(truthy(ASMValueHolder::Array(vec![0.into()])));
+    assert!(!truthy(ASMValueHolder::Array(Vec::new())));
+    assert!(!truthy(ASMValueHolder::Invalid));
+}
+
+#[test]
+fn references_are_always_truthy() {
+    assert!(truthy(ASMValueHolder::Register("a".into())));
+    assert!(truthy(ASMValueHolder::Label("loop".into())));
+    assert!(truthy(ASMValueHolder::ResolvedLabel("loop".into(), 0)));
+}
+
+fn sum(left: ASMValueHolder, right: ASMValueHolder) -> ASMValueHolder {
+    (ASMValue::new_from_holder(left, None) + ASMValue::new_from_holder(right, None))
+        .get_value_holder()
+}
+
+#[test]
+fn every_integer_width_adds_in_its_own_width() {
+    assert_eq!(
+        sum(ASMValueHolder::Int(40), ASMValueHolder::I8(2)),
+        ASMValueHolder::Int(42)
+    );
+    assert_eq!(
+        sum(ASMValueHolder::I8(100), ASMValueHolder::Int(27)),
+        ASMValueHolder::I8(127)
+    );
+    assert_eq!(
+        sum(ASMValueHolder::U8(200), ASMValueHolder::U8(55)),
+        ASMValueHolder::U8(255)
+    );
+    assert_eq!(
+        sum(ASMValueHolder::I64(i64::MAX - 1), ASMValueHolder::Int(1)),
+        ASMValueHolder::I64(i64::MAX)
+    );
+    assert_eq!(
+        sum(ASMValueHolder::U8(255), ASMValueHolder::U8(1)),
+        ASMValueHolder::Invalid
+    );
+}
+
+fn add_to_i8(mode: ArithmeticMode, amount: i32) -> (Result<(), ASMError>, ASMValueHolder) {
+    let mut definition = machine()
+        .with_arithmetic_mode(mode)
+        .insert_register_with_default(
+            "n",
+            ASMValue::new_from_holder(ASMValueHolder::I8(120), None),
+        );
+    let result = definition.interpret_fresh(format!("add [n] {}", amount));
+    (result, register(&definition, "n"))
+}
+
+#[test]
+fn integer_addition_overflows_by_the_arithmetic_mode() {
+    let (result, _) = add_to_i8(ArithmeticMode::Checked, 10);
+    assert_eq!(result.unwrap_err().to_string(), "Integer overflow!");
+    assert_eq!(
+        add_to_i8(ArithmeticMode::Saturating, 10).1,
+        ASMValueHolder::I8(127)
+    );
+    assert_eq!(
+        add_to_i8(ArithmeticMode::Wrapping, 10).1,
+        ASMValueHolder::I8(-126)
+    );
+    assert_eq!(
+        add_to_i8(ArithmeticMode::Checked, -20).1,
+        ASMValueHolder::I8(100)
+    );
+}